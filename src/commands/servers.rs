@@ -1,17 +1,177 @@
 use crate::context::Context;
 use crate::errors::kind::ErrorKind;
 use crate::errors::Error;
+use crate::utils::ping;
+use crate::utils::runtime;
+use crate::v2ray::config::Strategy;
+use crate::v2ray::probe::Probe;
 use crate::v2ray::server::ServerType;
+use crate::workdir::dir::Dir;
+use crate::workdir::latency::Latencies;
+use crate::workdir::latency::Latency;
+use crate::workdir::selection::Selection;
+use crate::workdir::servers::Server;
+use crate::workdir::servers::Servers;
+use crate::workdir::settings::SelectionFallback;
 
-use dialoguer::{theme::ColorfulTheme, Select};
+use clap::Subcommand;
+use dialoguer::{theme::ColorfulTheme, MultiSelect, Select};
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use regex::Regex;
+use std::io::IsTerminal;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
 
-pub fn exec(ctx: &mut Context) -> Result<(), Error> {
-    let mut selections: Vec<&str> = vec![];
+#[derive(Subcommand)]
+pub enum Commands {
+    /// print the servers numbered, as picked by `servers use`
+    List {
+        /// only list servers of the group
+        #[clap(long)]
+        group: Option<String>,
+        /// only list servers whose name matches the regex
+        #[clap(long = "match")]
+        pattern: Option<String>,
+    },
+    /// use a server without prompting
+    Use {
+        /// name of the server, or its number in `servers list` with the
+        /// same --group and --match
+        #[clap(required_unless_present = "pattern")]
+        server: Option<String>,
+        /// only pick from servers of the group
+        #[clap(long)]
+        group: Option<String>,
+        /// use the server whose name matches the regex
+        #[clap(long = "match")]
+        pattern: Option<String>,
+    },
+    /// print the share link of a server
+    Export {
+        name: String,
+        /// also render the link as a QR code
+        #[clap(long)]
+        qr: bool,
+    },
+    /// remove servers with the same endpoint and credential as an earlier one
+    Dedupe {
+        /// only list the duplicates
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// measure tcp connect time, and optionally tls handshake time, of servers
+    Ping {
+        /// only ping servers of the group
+        #[clap(long)]
+        group: Option<String>,
+        /// also measure the tls handshake of tls servers
+        #[clap(long)]
+        tls: bool,
+        /// timeout of each step in seconds
+        #[clap(long, default_value = "5")]
+        timeout: u64,
+        /// how many servers are pinged at the same time
+        #[clap(long, default_value_t = CONCURRENCY)]
+        concurrency: usize,
+        /// also request a url through each server with a temporary v2ray
+        #[clap(long)]
+        real: bool,
+        /// url requested with --real, defaults to probe.url in settings.yaml
+        #[clap(long)]
+        url: Option<String>,
+    },
+    /// use the fastest healthy server
+    Auto {
+        /// only pick from servers of the group
+        #[clap(long)]
+        group: Option<String>,
+        /// only pick from servers whose name matches the regex
+        #[clap(long = "match")]
+        pattern: Option<String>,
+        /// rank by the delay through a temporary v2ray instead of tcp connect time
+        #[clap(long)]
+        real: bool,
+        /// rank by the results of the last ping instead of measuring again
+        #[clap(long)]
+        cached: bool,
+    },
+    /// spread traffic over several servers, v2ray fails over between them
+    Balance {
+        /// names of the servers, picked interactively when neither names
+        /// nor --match are given
+        names: Vec<String>,
+        /// only pick from servers of the group
+        #[clap(long)]
+        group: Option<String>,
+        /// use the servers whose name matches the regex
+        #[clap(long = "match")]
+        pattern: Option<String>,
+        /// how v2ray picks a server for each connection, only least-ping
+        /// leaves out failing servers
+        #[clap(long, value_enum, default_value = "least-ping")]
+        strategy: Strategy,
+    },
+}
+
+/// How many servers are measured at the same time by default
+const CONCURRENCY: usize = 32;
+
+pub fn exec(ctx: &mut Context, command: &Option<Commands>) -> Result<(), Error> {
+    return match command {
+        None => select(ctx),
+        Some(Commands::List { group, pattern }) => list(ctx, group.as_deref(), pattern.as_deref()),
+        Some(Commands::Use {
+            server,
+            group,
+            pattern,
+        }) => use_matching(ctx, server.as_deref(), group.as_deref(), pattern.as_deref()),
+        Some(Commands::Export { name, qr }) => export(ctx, name.as_str(), *qr),
+        Some(Commands::Dedupe { dry_run }) => dedupe(ctx, *dry_run),
+        Some(Commands::Ping {
+            group,
+            tls,
+            timeout,
+            concurrency,
+            real,
+            url,
+        }) => {
+            let probe = real.then(|| new_probe(ctx, url.clone()));
+            ping(
+                ctx,
+                group.as_deref(),
+                *tls,
+                Duration::from_secs(*timeout),
+                *concurrency,
+                probe,
+            )
+        }
+        Some(Commands::Auto {
+            group,
+            pattern,
+            real,
+            cached,
+        }) => auto(ctx, group.as_deref(), pattern.as_deref(), *real, *cached),
+        Some(Commands::Balance {
+            names,
+            group,
+            pattern,
+            strategy,
+        }) => balance(ctx, names, group.as_deref(), pattern.as_deref(), *strategy),
+    };
+}
 
+pub fn select(ctx: &mut Context) -> Result<(), Error> {
+    let mut selections: Vec<String> = vec![];
+
+    let latencies = Latencies::load(ctx.dir);
     for server in ctx.servers.iter() {
-        match &server.server {
-            ServerType::Vmess(server) => selections.push(server.name.as_str()),
-            ServerType::Trojan(server) => selections.push(server.name.as_str()),
+        match latencies.get(&server.server.identity()) {
+            Some(latency) => {
+                selections.push(format!("{} ({})", server.server.name(), latency.label()))
+            }
+            None => selections.push(String::from(server.server.name())),
         }
     }
 
@@ -30,28 +190,727 @@ pub fn exec(ctx: &mut Context) -> Result<(), Error> {
     let server = ctx
         .servers
         .get(selection)
-        .expect(" server selection out of bounds");
+        .expect(" server selection out of bounds")
+        .clone();
 
-    let result = ctx.config.use_server(&server.server);
-    if result.is_err() {}
+    use_server(ctx, &server, true)?;
 
-    let result = ctx.v2ray_process.restart(ctx.settings.v2ray_binary());
+    println!(
+        "use server: {}, {}",
+        server.server.name(),
+        server.server.address()
+    );
+
+    return Ok(());
+}
+
+/// Prints the servers with the numbers `servers use` picks them by
+pub fn list(ctx: &Context, group: Option<&str>, pattern: Option<&str>) -> Result<(), Error> {
+    let servers = filter_servers(ctx.servers, group, pattern)?;
+    if servers.is_empty() {
+        println!("no servers");
+        return Ok(());
+    }
+
+    for (i, server) in servers.iter().enumerate() {
+        println!(
+            "{:>4}  {:<16} {}",
+            i + 1,
+            server.group,
+            server.server.name()
+        );
+    }
+    Ok(())
+}
+
+/// Uses the one server named by `query` or matched by `pattern`, fails when
+/// none or several match
+pub fn use_matching(
+    ctx: &mut Context,
+    query: Option<&str>,
+    group: Option<&str>,
+    pattern: Option<&str>,
+) -> Result<(), Error> {
+    let candidates = filter_servers(ctx.servers, group, pattern)?;
+    let server = match query {
+        Some(query) => pick(&candidates, query)?,
+        None => only(&candidates, pattern.unwrap_or_default())?,
+    }
+    .clone();
+
+    use_server(ctx, &server, true)?;
 
+    println!(
+        "use server: {}, {}",
+        server.server.name(),
+        server.server.address()
+    );
+    Ok(())
+}
+
+/// Finds the server by name, or by its number in `servers list` when no
+/// server has that name
+fn pick<'a>(candidates: &[&'a Server], query: &str) -> Result<&'a Server, Error> {
+    let named: Vec<&Server> = candidates
+        .iter()
+        .filter(|s| s.server.name() == query)
+        .copied()
+        .collect();
+    if !named.is_empty() {
+        return only(&named, query);
+    }
+
+    match query.parse::<usize>() {
+        Ok(index) if index >= 1 && index <= candidates.len() => Ok(candidates[index - 1]),
+        _ => only(&[], query),
+    }
+}
+
+fn only<'a>(matches: &[&'a Server], query: &str) -> Result<&'a Server, Error> {
+    match matches {
+        [server] => Ok(server),
+        [] => Err(Error {
+            kind: ErrorKind::ServerNotFound,
+            message: format!("no server matches {}", query),
+        }),
+        _ => {
+            let names: Vec<String> = matches
+                .iter()
+                .map(|s| format!("{} ({})", s.server.name(), s.group))
+                .collect();
+            Err(Error {
+                kind: ErrorKind::AmbiguousServer,
+                message: format!(
+                    "{} servers match {}: {}",
+                    matches.len(),
+                    query,
+                    names.join(", ")
+                ),
+            })
+        }
+    }
+}
+
+/// Writes the server to v2ray.json, remembers it as the selected one and
+/// restarts v2ray, when `restart` is false v2ray is only restarted if running
+pub fn use_server(ctx: &mut Context, server: &Server, restart: bool) -> Result<(), Error> {
+    use_servers(
+        ctx,
+        std::slice::from_ref(server),
+        Strategy::default(),
+        restart,
+    )
+}
+
+/// Like `use_server`, but several servers are written behind a balancer
+pub fn use_servers(
+    ctx: &mut Context,
+    servers: &[Server],
+    strategy: Strategy,
+    restart: bool,
+) -> Result<(), Error> {
+    let selection = Selection::balance(servers, strategy)?;
+    let outbounds: Vec<ServerType> = servers.iter().map(|s| s.server.clone()).collect();
+    ctx.config
+        .use_servers(&outbounds, strategy, ctx.settings.probe.url.as_str())?;
+    selection.save(ctx.dir)?;
+
+    if !restart && !ctx.v2ray_process.exist() {
+        return Ok(());
+    }
+
+    let result = ctx.v2ray_process.restart(ctx.settings.v2ray_binary());
     if result.is_err() {
         return Err(Error {
             kind: ErrorKind::ExecuteCommandError,
             message: format!("restart v2ray err: {}", result.err().unwrap()),
         });
     }
+    Ok(())
+}
+
+/// Points v2ray.json at the selected servers again after the servers were
+/// refreshed. Returns true when the outbounds were rewritten.
+pub fn reapply_selection(ctx: &mut Context) -> Result<bool, Error> {
+    let selection = match Selection::load(ctx.dir) {
+        Some(selection) => selection,
+        None => return Ok(false),
+    };
+
+    let mut changed = false;
+    let mut servers: Vec<Server> = vec![];
+    for member in selection.members() {
+        match member.locate(ctx.servers) {
+            Some(server) if member.is(server) => servers.push(server.clone()),
+            Some(server) => {
+                println!(
+                    "selected server {} changed, re-applying it",
+                    member.server.name()
+                );
+                changed = true;
+                servers.push(server.clone());
+            }
+            None => {
+                println!(
+                    "warning: selected server {} ({}) is gone from group {}",
+                    member.server.name(),
+                    member.server.identity(),
+                    member.group
+                );
+                changed = true;
+            }
+        }
+    }
+    if !changed {
+        return Ok(false);
+    }
 
-    match &server.server {
-        ServerType::Vmess(server) => {
-            println!("use server: {}, {}", server.name, server.address);
+    if servers.is_empty() {
+        let fallback = match ctx.settings.selection_fallback {
+            SelectionFallback::None => None,
+            SelectionFallback::Group => ctx.servers.iter().find(|s| s.group == selection.group),
+            SelectionFallback::Any => ctx.servers.iter().next(),
+        };
+        match fallback {
+            Some(server) => {
+                println!("falling back to server {}", server.server.name());
+                servers.push(server.clone());
+            }
+            None => return Ok(false),
         }
-        ServerType::Trojan(server) => {
-            println!("use server: {}, {}", server.name, server.address);
+    }
+
+    let strategy = selection.strategy.unwrap_or_default();
+    use_servers(ctx, &servers, strategy, false)?;
+    Ok(true)
+}
+
+/// Writes several servers behind a balancer, picked by name, by regex or
+/// interactively
+pub fn balance(
+    ctx: &mut Context,
+    names: &[String],
+    group: Option<&str>,
+    pattern: Option<&str>,
+    strategy: Strategy,
+) -> Result<(), Error> {
+    let candidates: Vec<Server> = filter_servers(ctx.servers, group, pattern)?
+        .into_iter()
+        .cloned()
+        .collect();
+
+    let servers: Vec<Server> = if !names.is_empty() {
+        let mut servers = vec![];
+        for name in names {
+            match candidates.iter().find(|s| s.server.name() == name) {
+                Some(server) => servers.push(server.clone()),
+                None => {
+                    return Err(Error {
+                        kind: ErrorKind::ServerNotFound,
+                        message: format!("server {} not found", name),
+                    })
+                }
+            }
         }
+        servers
+    } else if pattern.is_some() || candidates.is_empty() {
+        candidates
+    } else if !std::io::stdin().is_terminal() {
+        // the prompt would spin on a closed stdin instead of failing
+        return Err(Error {
+            kind: ErrorKind::ExecuteCommandError,
+            message: String::from("stdin is not a terminal, pass server names or --match"),
+        });
+    } else {
+        let items: Vec<&str> = candidates.iter().map(|s| s.server.name()).collect();
+        let picked = match MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Pick servers")
+            .items(&items[..])
+            .interact()
+        {
+            Ok(picked) => picked,
+            Err(err) => {
+                return Err(Error {
+                    kind: ErrorKind::ExecuteCommandError,
+                    message: format!("pick servers err: {}", err),
+                })
+            }
+        };
+        picked.into_iter().map(|i| candidates[i].clone()).collect()
+    };
+
+    if servers.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::ServerNotFound,
+            message: String::from("no servers to balance"),
+        });
     }
 
-    return Ok(());
+    use_servers(ctx, &servers, strategy, true)?;
+
+    for server in servers.iter() {
+        println!(
+            "use server: {}, {}",
+            server.server.name(),
+            server.server.address()
+        );
+    }
+    if servers.len() > 1 {
+        println!("balanced with strategy {:?}", strategy);
+    }
+    Ok(())
+}
+
+pub fn export(ctx: &Context, name: &str, qr: bool) -> Result<(), Error> {
+    let server = match ctx.servers.iter().find(|s| s.server.name() == name) {
+        Some(server) => server,
+        None => {
+            return Err(Error {
+                kind: ErrorKind::ServerNotFound,
+                message: format!("server {} not found", name),
+            })
+        }
+    };
+
+    let link = server.server.to_url();
+    println!("{}", link);
+
+    if qr {
+        let code = match QrCode::new(link.as_bytes()) {
+            Ok(code) => code,
+            Err(err) => {
+                return Err(Error {
+                    kind: ErrorKind::ExecuteCommandError,
+                    message: format!("encode qr code err: {}", err),
+                })
+            }
+        };
+
+        // dark modules are drawn in the terminal background color
+        let image = code
+            .render::<unicode::Dense1x2>()
+            .dark_color(unicode::Dense1x2::Light)
+            .light_color(unicode::Dense1x2::Dark)
+            .build();
+        println!("{}", image);
+    }
+
+    Ok(())
+}
+
+pub fn dedupe(ctx: &mut Context, dry_run: bool) -> Result<(), Error> {
+    let duplicates: Vec<Server> = match dry_run {
+        true => ctx
+            .servers
+            .duplicates()
+            .into_iter()
+            .filter_map(|idx| ctx.servers.get(idx).cloned())
+            .collect(),
+        false => ctx.servers.dedupe(),
+    };
+
+    if duplicates.is_empty() {
+        println!("no duplicate servers");
+        return Ok(());
+    }
+
+    for server in duplicates.iter() {
+        println!(
+            "{}/{} ({})",
+            server.group,
+            server.server.name(),
+            server.server.identity()
+        );
+    }
+
+    if dry_run {
+        println!("{} duplicate servers", duplicates.len());
+        return Ok(());
+    }
+
+    ctx.servers.save()?;
+    println!("removed {} duplicate servers", duplicates.len());
+    reapply_selection(ctx)?;
+    Ok(())
+}
+
+pub fn ping(
+    ctx: &mut Context,
+    group: Option<&str>,
+    tls: bool,
+    timeout: Duration,
+    concurrency: usize,
+    probe: Option<Probe>,
+) -> Result<(), Error> {
+    let servers: Vec<Server> = filter_servers(ctx.servers, group, None)?
+        .into_iter()
+        .cloned()
+        .collect();
+    if servers.is_empty() {
+        println!("no servers");
+        return Ok(());
+    }
+
+    let results = measure_servers(&servers, tls, timeout, concurrency, probe)?;
+    record_latencies(ctx.dir, &results)?;
+    for (server, latency) in servers.iter().zip(results) {
+        let ms = |value: Option<u64>| value.map_or(String::from("-"), |v| format!("{} ms", v));
+        println!(
+            "{:<16} {:<32} {:>8} {:>8} {:>8}  {}",
+            server.group,
+            server.server.name(),
+            ms(latency.tcp),
+            ms(latency.tls),
+            ms(latency.delay),
+            latency.error.as_deref().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+/// Servers of the group whose names match the pattern
+pub fn filter_servers<'a>(
+    servers: &'a Servers,
+    group: Option<&str>,
+    pattern: Option<&str>,
+) -> Result<Vec<&'a Server>, Error> {
+    let pattern = match pattern.map(Regex::new) {
+        Some(Ok(re)) => Some(re),
+        Some(Err(err)) => {
+            return Err(Error {
+                kind: ErrorKind::InvalidRegex,
+                message: format!("invalid regex {}: {}", pattern.unwrap(), err),
+            })
+        }
+        None => None,
+    };
+
+    Ok(servers
+        .iter()
+        .filter(|s| group.is_none_or(|group| s.group == group))
+        .filter(|s| {
+            pattern
+                .as_ref()
+                .is_none_or(|re| re.is_match(s.server.name()))
+        })
+        .collect())
+}
+
+/// Measures the servers concurrently, the results are in the order of
+/// `servers`
+pub fn measure_servers(
+    servers: &[Server],
+    tls: bool,
+    timeout: Duration,
+    concurrency: usize,
+    probe: Option<Probe>,
+) -> Result<Vec<Latency>, Error> {
+    let runtime = runtime::new()?;
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let probe = probe.map(Arc::new);
+    let results: Vec<Latency> = runtime.block_on(async {
+        let handles: Vec<_> = servers
+            .iter()
+            .map(|server| {
+                let semaphore = semaphore.clone();
+                let server = server.server.clone();
+                let probe = probe.clone();
+                tokio::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await.unwrap();
+                    measure(&server, tls, timeout, probe.as_deref()).await
+                })
+            })
+            .collect();
+
+        let mut results = vec![];
+        for (handle, server) in handles.into_iter().zip(servers.iter()) {
+            results.push(handle.await.unwrap_or_else(|err| {
+                let mut latency = Latency::new(server.server.identity());
+                latency.error = Some(format!("ping task err: {}", err));
+                latency
+            }));
+        }
+        results
+    });
+
+    Ok(results)
+}
+
+/// Keeps the results in latency.yaml, where they are shown when picking a
+/// server and ranked by `--cached`
+pub fn record_latencies(workdir: &Dir, results: &[Latency]) -> Result<(), Error> {
+    let mut latencies = Latencies::load(workdir);
+    for latency in results.iter() {
+        latencies.set(latency.clone());
+    }
+    latencies.save(workdir)
+}
+
+/// Uses the fastest healthy server of the group whose name matches the
+/// pattern and restarts v2ray
+pub fn auto(
+    ctx: &mut Context,
+    group: Option<&str>,
+    pattern: Option<&str>,
+    real: bool,
+    cached: bool,
+) -> Result<(), Error> {
+    let (server, ms) = fastest(ctx, group, pattern, real, cached)?;
+    use_server(ctx, &server, true)?;
+
+    println!(
+        "use server: {}, {} ({} ms)",
+        server.server.name(),
+        server.server.address(),
+        ms
+    );
+    Ok(())
+}
+
+/// Finds the healthy server with the lowest tcp connect time, or the lowest
+/// delay through a temporary v2ray with `real`. `cached` ranks by the
+/// results in latency.yaml instead of measuring again.
+pub fn fastest(
+    ctx: &Context,
+    group: Option<&str>,
+    pattern: Option<&str>,
+    real: bool,
+    cached: bool,
+) -> Result<(Server, u64), Error> {
+    let candidates: Vec<Server> = filter_servers(ctx.servers, group, pattern)?
+        .into_iter()
+        .cloned()
+        .collect();
+    if candidates.is_empty() {
+        return Err(Error {
+            kind: ErrorKind::ServerNotFound,
+            message: String::from("no servers match"),
+        });
+    }
+    fastest_of(ctx, candidates, real, cached)
+}
+
+/// Finds the fastest healthy server among the candidates, see `fastest`
+pub fn fastest_of(
+    ctx: &Context,
+    candidates: Vec<Server>,
+    real: bool,
+    cached: bool,
+) -> Result<(Server, u64), Error> {
+    let results: Vec<Option<Latency>> = match cached {
+        true => {
+            let latencies = Latencies::load(ctx.dir);
+            candidates
+                .iter()
+                .map(|s| latencies.get(&s.server.identity()).cloned())
+                .collect()
+        }
+        false => {
+            let results = measure_candidates(ctx, &candidates, real)?;
+            record_latencies(ctx.dir, &results)?;
+            results.into_iter().map(Some).collect()
+        }
+    };
+
+    rank(candidates, results, real).ok_or(Error {
+        kind: ErrorKind::ServerNotFound,
+        message: String::from("no healthy servers"),
+    })
+}
+
+/// Measures the servers the way `fastest` ranks them, without keeping the
+/// results
+pub fn measure_candidates(
+    ctx: &Context,
+    servers: &[Server],
+    real: bool,
+) -> Result<Vec<Latency>, Error> {
+    let timeout = Duration::from_secs(ctx.settings.probe.timeout);
+    let probe = real.then(|| new_probe(ctx, None));
+    measure_servers(servers, false, timeout, CONCURRENCY, probe)
+}
+
+/// Picks the server with the lowest tcp connect time, or delay with `real`,
+/// among those whose latest result has no error
+pub fn rank(
+    candidates: Vec<Server>,
+    results: Vec<Option<Latency>>,
+    real: bool,
+) -> Option<(Server, u64)> {
+    candidates
+        .into_iter()
+        .zip(results)
+        .filter_map(|(server, latency)| Some((server, ranked_ms(latency.as_ref()?, real)?)))
+        .min_by_key(|(_, ms)| *ms)
+}
+
+/// The time a server is ranked by, None when it failed
+pub fn ranked_ms(latency: &Latency, real: bool) -> Option<u64> {
+    if latency.error.is_some() {
+        return None;
+    }
+    match real {
+        true => latency.delay,
+        false => latency.tcp,
+    }
+}
+
+fn new_probe(ctx: &Context, url: Option<String>) -> Probe {
+    Probe {
+        bin: String::from(ctx.settings.v2ray_binary()),
+        url: url.unwrap_or(ctx.settings.probe.url.clone()),
+        timeout: Duration::from_secs(ctx.settings.probe.timeout),
+    }
+}
+
+async fn measure(
+    server: &ServerType,
+    tls: bool,
+    timeout: Duration,
+    probe: Option<&Probe>,
+) -> Latency {
+    let mut latency = Latency::new(server.identity());
+
+    let port = match u16::try_from(server.port()) {
+        Ok(port) => port,
+        Err(_) => {
+            latency.error = Some(format!("invalid port {}", server.port()));
+            return latency;
+        }
+    };
+    let stream = match ping::tcp_connect(server.address(), port, timeout).await {
+        Ok((stream, elapsed)) => {
+            latency.tcp = Some(elapsed.as_millis() as u64);
+            stream
+        }
+        Err(err) => {
+            latency.error = Some(err.message);
+            return latency;
+        }
+    };
+
+    if let (true, Some(server_name)) = (tls, server.tls_server_name()) {
+        match ping::tls_handshake(stream, server_name, timeout).await {
+            Ok(elapsed) => latency.tls = Some(elapsed.as_millis() as u64),
+            Err(err) => {
+                latency.error = Some(err.message);
+                return latency;
+            }
+        }
+    }
+
+    if let Some(probe) = probe {
+        match probe.delay(server).await {
+            Ok(elapsed) => latency.delay = Some(elapsed.as_millis() as u64),
+            Err(err) => latency.error = Some(err.message),
+        }
+    }
+    latency
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2ray::server::from_str;
+
+    fn server(link: &str) -> Server {
+        Server {
+            group: String::from("g"),
+            server: from_str(link).unwrap(),
+        }
+    }
+
+    fn latency(server: &Server, tcp: u64, delay: Option<u64>, error: bool) -> Option<Latency> {
+        let mut latency = Latency::new(server.server.identity());
+        latency.tcp = Some(tcp);
+        latency.delay = delay;
+        latency.error = error.then(|| String::from("timeout"));
+        Some(latency)
+    }
+
+    #[test]
+    fn rank_picks_fastest_healthy_server() {
+        let servers = vec![
+            server("trojan://p@a.example.com:443#A"),
+            server("trojan://p@b.example.com:443#B"),
+            server("trojan://p@c.example.com:443#C"),
+            server("trojan://p@d.example.com:443#D"),
+        ];
+        let results = vec![
+            latency(&servers[0], 80, Some(300), false),
+            latency(&servers[1], 10, None, true),
+            latency(&servers[2], 40, Some(500), false),
+            None,
+        ];
+
+        let (fastest, ms) = rank(servers.clone(), results.clone(), false).unwrap();
+        assert_eq!((fastest.server.name(), ms), ("C", 40));
+
+        let (fastest, ms) = rank(servers.clone(), results, true).unwrap();
+        assert_eq!((fastest.server.name(), ms), ("A", 300));
+
+        assert!(rank(servers, vec![None, None, None, None], false).is_none());
+    }
+
+    #[test]
+    fn pick_server_by_name_or_position() {
+        let servers = [
+            server("trojan://p@a.example.com:443#A"),
+            server("trojan://p@b.example.com:443#B"),
+            server("trojan://p@c.example.com:443#B"),
+            server("trojan://p@d.example.com:443#2"),
+        ];
+        let candidates: Vec<&Server> = servers.iter().collect();
+
+        assert_eq!(pick(&candidates, "A").unwrap().server.name(), "A");
+        assert_eq!(pick(&candidates, "1").unwrap().server.name(), "A");
+        // a name wins over a position
+        assert_eq!(
+            pick(&candidates, "2").unwrap().server.address(),
+            "d.example.com"
+        );
+
+        let err = pick(&candidates, "B").unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::AmbiguousServer));
+        assert!(err.message.contains("2 servers match B"));
+
+        for query in ["C", "0", "5"] {
+            let err = pick(&candidates, query).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::ServerNotFound));
+        }
+    }
+
+    #[test]
+    fn filter_servers_by_group_and_pattern() {
+        let yaml = serde_yaml::to_string(&serde_json::json!({
+            "servers": [
+                server("trojan://p@a.example.com:443#HK 01"),
+                server("trojan://p@b.example.com:443#JP 01"),
+                Server {
+                    group: String::from("h"),
+                    server: from_str("trojan://p@c.example.com:443#HK 02").unwrap(),
+                },
+            ]
+        }))
+        .unwrap();
+        let servers: Servers = serde_yaml::from_str(yaml.as_str()).unwrap();
+
+        let names = |found: Vec<&Server>| -> Vec<String> {
+            found
+                .iter()
+                .map(|s| String::from(s.server.name()))
+                .collect()
+        };
+        assert_eq!(
+            names(filter_servers(&servers, None, Some("^HK")).unwrap()),
+            vec!["HK 01", "HK 02"]
+        );
+        assert_eq!(
+            names(filter_servers(&servers, Some("g"), Some("HK")).unwrap()),
+            vec!["HK 01"]
+        );
+
+        let err = filter_servers(&servers, None, Some("HK(")).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidRegex));
+    }
 }
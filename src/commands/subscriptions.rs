@@ -1,36 +1,106 @@
 extern crate base64;
 
+use crate::commands::servers;
 use crate::context::Context;
 use crate::errors;
 use crate::errors::Error;
-use crate::v2ray;
-use crate::v2ray::server::*;
+use crate::utils::runtime;
+use crate::v2ray::config::Config;
+use crate::v2ray::subscription;
+use crate::workdir::cache::SubscriptionCache;
+use crate::workdir::dir::Dir;
+use crate::workdir::servers::Servers;
+use crate::workdir::settings::format_bytes;
+use crate::workdir::settings::Interval;
+use crate::workdir::settings::ServerFilter;
 use crate::workdir::settings::Subscription;
+use crate::workdir::settings::SubscriptionDefaults;
+use crate::workdir::settings::Usage;
+use crate::workdir::settings::Via;
 use std::time::Duration;
 
 use clap::Subcommand;
-use std::str;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::time::SystemTime;
 
-use reqwest::blocking::ClientBuilder;
+use reqwest_middleware::ClientBuilder;
+use reqwest_retry::policies::ExponentialBackoff;
+use reqwest_retry::RetryTransientMiddleware;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+use tokio::sync::Semaphore;
 
 #[derive(Subcommand)]
 pub enum Commands {
-    Add { name: String, url: String },
-    Remove { name: String },
-    // List {},
+    Add {
+        name: String,
+        url: String,
+        /// fetch the subscription right after adding it
+        #[clap(long)]
+        update_now: bool,
+    },
+    Remove {
+        name: String,
+    },
+    List {
+        /// print subscriptions as json
+        #[clap(long)]
+        json: bool,
+    },
     Update {},
+    /// update the subscription on `subscriptions update`
+    Enable {
+        name: String,
+    },
+    /// skip the subscription on `subscriptions update`
+    Disable {
+        name: String,
+    },
+    Rename {
+        name: String,
+        new_name: String,
+    },
+    SetUrl {
+        name: String,
+        url: String,
+    },
+    /// fetch the subscription directly, through the local v2ray inbound
+    /// or through a proxy url, `default` follows subscription_defaults.via
+    SetVia {
+        name: String,
+        via: String,
+    },
+    /// how often `v2up work` refreshes the subscription, e.g. 6h,
+    /// `default` follows subscription_defaults.update_interval
+    SetInterval {
+        name: String,
+        interval: String,
+    },
 }
 
 pub fn exec(ctx: &mut Context, commands: &Commands) -> Result<(), Error> {
     return match commands {
-        Commands::Add { name, url } => add(ctx, name.as_str(), url.as_str()),
+        Commands::Add {
+            name,
+            url,
+            update_now,
+        } => add(ctx, name.as_str(), url.as_str(), *update_now),
         Commands::Remove { name } => remove(ctx, name.as_str()),
+        Commands::List { json } => list(ctx, *json),
         Commands::Update {} => update(ctx),
+        Commands::Enable { name } => set_skip_update(ctx, name.as_str(), false),
+        Commands::Disable { name } => set_skip_update(ctx, name.as_str(), true),
+        Commands::Rename { name, new_name } => rename(ctx, name.as_str(), new_name.as_str()),
+        Commands::SetUrl { name, url } => set_url(ctx, name.as_str(), url.as_str()),
+        Commands::SetVia { name, via } => set_via(ctx, name.as_str(), via.as_str()),
+        Commands::SetInterval { name, interval } => {
+            set_interval(ctx, name.as_str(), interval.as_str())
+        }
     };
 }
 
-pub fn add(ctx: &mut Context, name: &str, url: &str) -> Result<(), Error> {
+pub fn add(ctx: &mut Context, name: &str, url: &str, update_now: bool) -> Result<(), Error> {
     let now = SystemTime::now();
 
     ctx.settings.add_subscription(Subscription {
@@ -38,124 +108,922 @@ pub fn add(ctx: &mut Context, name: &str, url: &str) -> Result<(), Error> {
         url: String::from(url),
         added_at: chrono::DateTime::from(now),
         last_updated_at: chrono::DateTime::from(std::time::UNIX_EPOCH),
-        skip_update: true,
-    })
+        last_checked_at: None,
+        skip_update: false,
+        update_interval: None,
+        usage: None,
+        via: None,
+        user_agent: None,
+        headers: BTreeMap::new(),
+        timeout: None,
+        filter: ServerFilter::default(),
+    })?;
+
+    if update_now {
+        let results = update_subscriptions(ctx, &[String::from(name)])?;
+        if let Some((_, UpdateResult::Failed(err))) = results.first() {
+            println!("subscription {} update failed: {}", name, err);
+        }
+
+        ctx.servers.save()?;
+        ctx.settings.save()?;
+    }
+
+    Ok(())
 }
 
 pub fn update(ctx: &mut Context) -> Result<(), Error> {
-    for sub in &mut ctx.settings.subscriptions {
-        if sub.skip_update {
+    let names: Vec<String> = ctx
+        .settings
+        .subscriptions
+        .iter()
+        .filter(|sub| !sub.skip_update)
+        .map(|sub| sub.name.clone())
+        .collect();
+    let results = update_subscriptions(ctx, &names)?;
+
+    ctx.servers.save().expect("fail to save servers.yaml");
+    ctx.settings.save().expect("fail to save settings.yaml");
+
+    print_summary(&results);
+    servers::reapply_selection(ctx)?;
+    Ok(())
+}
+
+/// Fetches the subscriptions whose update interval has passed without
+/// changing the workdir, so that the worker can do it unlocked
+pub fn fetch_due(ctx: &Context) -> Result<Vec<Pending>, Error> {
+    let now = chrono::Local::now();
+    let defaults = &ctx.settings.subscription_defaults;
+    let names: Vec<String> = ctx
+        .settings
+        .subscriptions
+        .iter()
+        .filter(|sub| sub.is_due(defaults, now))
+        .map(|sub| sub.name.clone())
+        .collect();
+    if names.is_empty() {
+        return Ok(vec![]);
+    }
+    fetch_subscriptions(ctx, &names)
+}
+
+/// Merges subscriptions fetched by `fetch_due` into a freshly loaded
+/// workdir, returns the results and whether the selected server was
+/// re-applied
+pub fn merge_due(
+    ctx: &mut Context,
+    pending: Vec<Pending>,
+) -> Result<(Vec<(String, UpdateResult)>, bool), Error> {
+    let results = merge_subscriptions(ctx, pending);
+    if results.is_empty() {
+        return Ok((results, false));
+    }
+    ctx.servers.save()?;
+    ctx.settings.save()?;
+
+    let reapplied = servers::reapply_selection(ctx)?;
+    Ok((results, reapplied))
+}
+
+/// Fetches the named subscriptions concurrently, then merges them into
+/// the server groups one by one in the order of settings.yaml
+fn update_subscriptions(
+    ctx: &mut Context,
+    names: &[String],
+) -> Result<Vec<(String, UpdateResult)>, Error> {
+    let pending = fetch_subscriptions(ctx, names)?;
+    Ok(merge_subscriptions(ctx, pending))
+}
+
+/// A fetched subscription, not merged into the servers yet
+pub struct Pending {
+    name: String,
+    url: String,
+    cached: Option<SubscriptionCache>,
+    fetched: Result<Fetched, Error>,
+}
+
+/// Fetches the named subscriptions concurrently, the results are in the
+/// order of settings.yaml
+fn fetch_subscriptions(ctx: &Context, names: &[String]) -> Result<Vec<Pending>, Error> {
+    let runtime = runtime::new()?;
+    let defaults = &ctx.settings.subscription_defaults;
+
+    let mut jobs = vec![];
+    let mut pending = vec![];
+    for sub in ctx.settings.subscriptions.iter() {
+        if !names.contains(&sub.name) {
             continue;
         }
 
-        match fetch(sub.url.as_str()) {
-            Ok(servers) => {
-                match ctx
-                    .servers
-                    .update_by_group_name(sub.name.as_str(), &servers)
-                {
-                    Ok(changed) => {
-                        if changed {
-                            sub.last_updated_at = chrono::DateTime::from(SystemTime::now())
-                        }
-                    }
-                    Err(err) => {
-                        println!("update servers by subscription {} err: {}", sub.name, err)
-                    }
-                }
+        let cached = SubscriptionCache::load(ctx.dir, sub.name.as_str());
+        let failure = match FetchOptions::new(defaults, sub, ctx.config) {
+            Ok(options) => {
+                jobs.push(FetchJob {
+                    sub: sub.clone(),
+                    cache: cached.clone(),
+                    options,
+                });
+                None
+            }
+            Err(err) => Some(err),
+        };
+        pending.push((sub.name.clone(), sub.url.clone(), cached, failure));
+    }
+
+    let mut fetched = fetch_all(&runtime, jobs, defaults.concurrency).into_iter();
+    Ok(pending
+        .into_iter()
+        .map(|(name, url, cached, failure)| match failure {
+            // without a way to reach the url the cached copy is not used either
+            Some(err) => Pending {
+                name,
+                url,
+                cached: None,
+                fetched: Err(err),
+            },
+            None => Pending {
+                name,
+                url,
+                cached,
+                fetched: fetched.next().unwrap(),
+            },
+        })
+        .collect())
+}
+
+/// Merges the fetched subscriptions into the server groups. Subscriptions
+/// removed or pointed at another url since they were fetched are skipped.
+fn merge_subscriptions(ctx: &mut Context, pending: Vec<Pending>) -> Vec<(String, UpdateResult)> {
+    let skip_duplicates = ctx.settings.subscription_defaults.skip_duplicates;
+
+    let mut results = vec![];
+    for pending in pending {
+        let sub = ctx
+            .settings
+            .subscriptions
+            .iter_mut()
+            .find(|sub| sub.name == pending.name && sub.url == pending.url);
+        let sub = match sub {
+            Some(sub) => sub,
+            None => {
+                println!(
+                    "subscription {} changed while it was fetched, skipped",
+                    pending.name
+                );
                 continue;
             }
+        };
+
+        sub.last_checked_at = Some(chrono::Local::now());
+        let result = update_subscription(
+            ctx.dir,
+            ctx.servers,
+            sub,
+            pending.cached,
+            pending.fetched,
+            skip_duplicates,
+        );
+        results.push((pending.name, result));
+    }
+
+    results
+}
+
+/// Outcome of updating one subscription
+pub enum UpdateResult {
+    Updated(usize),
+    Unchanged,
+    Failed(String),
+}
+
+impl fmt::Display for UpdateResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UpdateResult::Updated(count) => write!(f, "updated ({} servers)", count),
+            UpdateResult::Unchanged => write!(f, "unchanged"),
+            UpdateResult::Failed(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+fn print_summary(results: &[(String, UpdateResult)]) {
+    if results.is_empty() {
+        println!("no subscriptions to update");
+        return;
+    }
+
+    println!("summary:");
+    for (name, result) in results.iter() {
+        match result {
+            UpdateResult::Updated(count) => println!("  updated    {} ({} servers)", name, count),
+            UpdateResult::Unchanged => println!("  unchanged  {}", name),
+            UpdateResult::Failed(err) => println!("  failed     {}: {}", name, err),
+        }
+    }
+}
+
+/// Replaces the server group of the subscription when the fetched
+/// content changed since the last good fetch
+fn update_subscription(
+    workdir: &Dir,
+    servers: &mut Servers,
+    sub: &mut Subscription,
+    cached: Option<SubscriptionCache>,
+    fetched: Result<Fetched, Error>,
+    skip_duplicates: bool,
+) -> UpdateResult {
+    let mut failure = None;
+    let fetched = match fetched {
+        Ok(fetched) => Some(fetched),
+        Err(err) if cached.is_some() => {
+            println!(
+                "subscription {}: {}, falling back to cached copy",
+                sub.name, err
+            );
+            failure = Some(format!("{}, used cached copy", err));
+            None
+        }
+        Err(err) => {
+            println!("subscription {}: {}", sub.name, err);
+            return UpdateResult::Failed(err.message);
+        }
+    };
+
+    if let Some(usage) = fetched.as_ref().and_then(|fetched| fetched.usage.clone()) {
+        sub.usage = Some(usage);
+    }
+    if let Some(warning) = sub.usage.as_ref().and_then(|usage| usage.warning()) {
+        println!("warning: subscription {} {}", sub.name, warning);
+    }
+
+    // the cached copy is merged again when nothing new was fetched so that
+    // edited filters take effect, the diff tells whether anything changed.
+    // A response with the cached body but new validators is saved as well,
+    // so that the next request can be answered with 304
+    let response = fetched.and_then(|fetched| fetched.response);
+    let is_new = response.is_some() && response != cached;
+    let cache = match response.or(cached) {
+        Some(cache) => cache,
+        None => {
+            println!("subscription {}: unchanged", sub.name);
+            return match failure {
+                Some(failure) => UpdateResult::Failed(failure),
+                None => UpdateResult::Unchanged,
+            };
+        }
+    };
+
+    let (format, new_servers) =
+        match subscription::parse(cache.content_type.as_str(), cache.body.as_str()) {
+            Ok(result) => result,
             Err(err) => {
-                println!("request {} err: {}", sub.url, err);
-                continue;
+                println!("parse subscription {} err: {}", sub.name, err);
+                return UpdateResult::Failed(err.message);
             }
+        };
+    println!(
+        "subscription {}: {} servers in {} format",
+        sub.name,
+        new_servers.len(),
+        format
+    );
+
+    let diff = match servers.update_by_group_name(
+        sub.name.as_str(),
+        &new_servers,
+        &sub.filter,
+        skip_duplicates,
+    ) {
+        Ok(Some(diff)) => diff,
+        Ok(None) => {
+            return UpdateResult::Failed(String::from("no servers left after filtering"));
+        }
+        Err(err) => {
+            println!("update servers by subscription {} err: {}", sub.name, err);
+            return UpdateResult::Failed(err.message);
+        }
+    };
+
+    if is_new {
+        if let Err(err) = cache.save(workdir, sub.name.as_str()) {
+            println!("save subscription {} cache err: {}", sub.name, err);
         }
     }
 
-    ctx.servers.save().expect("fail to save servers.yaml");
-    ctx.settings.save().expect("fail to save settings.yaml");
+    if diff.is_empty() {
+        println!("subscription {}: unchanged", sub.name);
+        return match failure {
+            Some(failure) => UpdateResult::Failed(failure),
+            None => UpdateResult::Unchanged,
+        };
+    }
+
+    println!("subscription {}: {}", sub.name, diff);
+    sub.last_updated_at = chrono::DateTime::from(SystemTime::now());
+    match failure {
+        Some(failure) => UpdateResult::Failed(failure),
+        None => UpdateResult::Updated(servers.count_by_group(sub.name.as_str())),
+    }
+}
+
+pub fn set_skip_update(ctx: &mut Context, name: &str, skip_update: bool) -> Result<(), Error> {
+    let sub = ctx.settings.find_subscription_mut(name)?;
+    sub.skip_update = skip_update;
+    ctx.settings.save()?;
+
+    match skip_update {
+        true => println!("subscription {} disabled", name),
+        false => println!("subscription {} enabled", name),
+    }
     Ok(())
 }
 
-fn fetch(url: &str) -> Result<Vec<ServerType>, Error> {
-    let mut builder = ClientBuilder::new()
-        .timeout(Duration::new(30, 0))
-        .no_proxy();
+pub fn rename(ctx: &mut Context, name: &str, new_name: &str) -> Result<(), Error> {
+    ctx.settings.rename_subscription(name, new_name)?;
 
-    let client = builder.build().unwrap();
+    ctx.servers.rename_group(name, new_name);
+    ctx.servers.save()?;
+    SubscriptionCache::rename(ctx.dir, name, new_name);
 
-    let result = client.get(url).send();
-    if result.is_err() {
-        return match result.err() {
-            Some(err) => {
-                eprintln!("{}", err);
-                return Err(Error {
+    println!("subscription {} renamed to {}", name, new_name);
+    Ok(())
+}
+
+pub fn set_url(ctx: &mut Context, name: &str, url: &str) -> Result<(), Error> {
+    let sub = ctx.settings.find_subscription_mut(name)?;
+    sub.url = String::from(url);
+    ctx.settings.save()?;
+    // the cached copy and its validators belong to the old url
+    SubscriptionCache::remove(ctx.dir, name);
+
+    println!("subscription {} url set to {}", name, url);
+    Ok(())
+}
+
+pub fn set_via(ctx: &mut Context, name: &str, via: &str) -> Result<(), Error> {
+    let via = match via {
+        "default" => None,
+        _ => Some(Via::try_from(String::from(via))?),
+    };
+
+    let sub = ctx.settings.find_subscription_mut(name)?;
+    sub.via = via;
+    ctx.settings.save()?;
+
+    println!("subscription {} via set", name);
+    Ok(())
+}
+
+pub fn set_interval(ctx: &mut Context, name: &str, interval: &str) -> Result<(), Error> {
+    let interval = match interval {
+        "default" => None,
+        _ => Some(Interval::try_from(String::from(interval))?),
+    };
+
+    let sub = ctx.settings.find_subscription_mut(name)?;
+    sub.update_interval = interval;
+    ctx.settings.save()?;
+
+    println!("subscription {} update interval set", name);
+    Ok(())
+}
+
+/// How to reach a subscription url
+struct FetchOptions {
+    proxy: Option<String>,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl FetchOptions {
+    fn new(
+        defaults: &SubscriptionDefaults,
+        sub: &Subscription,
+        config: &Config,
+    ) -> Result<FetchOptions, Error> {
+        Ok(FetchOptions {
+            proxy: sub
+                .via
+                .as_ref()
+                .unwrap_or(&defaults.via)
+                .proxy_url(config)?,
+            timeout: Duration::from_secs(sub.timeout.unwrap_or(defaults.timeout)),
+            retries: defaults.retries,
+        })
+    }
+}
+
+/// A subscription fetch which can run on its own task
+struct FetchJob {
+    sub: Subscription,
+    cache: Option<SubscriptionCache>,
+    options: FetchOptions,
+}
+
+/// Runs the jobs with at most `concurrency` of them at the same time,
+/// the results are in the order of `jobs`
+fn fetch_all(
+    runtime: &Runtime,
+    jobs: Vec<FetchJob>,
+    concurrency: usize,
+) -> Vec<Result<Fetched, Error>> {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+
+    runtime.block_on(async {
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .map(|job| {
+                let semaphore = semaphore.clone();
+                tokio::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await.unwrap();
+                    fetch(&job.sub, job.cache.as_ref(), &job.options).await
+                })
+            })
+            .collect();
+
+        let mut results = vec![];
+        for handle in handles {
+            results.push(match handle.await {
+                Ok(result) => result,
+                Err(err) => Err(Error {
                     kind: errors::kind::ErrorKind::HTTPRequestError,
-                    message: format!("get {} with unknown err", url),
-                });
+                    message: format!("fetch task err: {}", err),
+                }),
+            });
+        }
+        results
+    })
+}
+
+struct Fetched {
+    /// None when the server answered 304 not modified
+    response: Option<SubscriptionCache>,
+    usage: Option<Usage>,
+}
+
+async fn fetch(
+    sub: &Subscription,
+    cache: Option<&SubscriptionCache>,
+    options: &FetchOptions,
+) -> Result<Fetched, Error> {
+    let url = sub.url.as_str();
+    let builder = reqwest::Client::builder().timeout(options.timeout);
+    let builder = match &options.proxy {
+        Some(proxy) => match reqwest::Proxy::all(proxy) {
+            Ok(proxy) => builder.proxy(proxy),
+            Err(err) => {
+                return Err(Error {
+                    kind: errors::kind::ErrorKind::InvalidProxy,
+                    message: format!("invalid proxy {}: {}", proxy, err),
+                })
             }
-            None => Err(Error {
-                kind: errors::kind::ErrorKind::HTTPRequestError,
-                message: format!("get {} with unknown err", url),
-            }),
-        };
+        },
+        None => builder.no_proxy(),
+    };
+
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(options.retries);
+    let client = ClientBuilder::new(builder.build().unwrap())
+        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+        .build();
+
+    let mut request = client.get(url);
+    if let Some(user_agent) = &sub.user_agent {
+        request = request.header(reqwest::header::USER_AGENT, user_agent.as_str());
+    }
+    for (name, value) in sub.headers.iter() {
+        request = request.header(name.as_str(), value.as_str());
+    }
+    if let Some(cache) = cache {
+        if let Some(etag) = &cache.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag.as_str());
+        }
+        if let Some(last_modified) = &cache.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified.as_str());
+        }
     }
 
-    let result = result.unwrap().text();
+    let result = request.send().await;
     if result.is_err() {
-        return match result.err() {
-            Some(err) => Err(Error {
-                kind: errors::kind::ErrorKind::HTTPRequestError,
-                message: format!("read body err: {}", err),
-            }),
-            None => Err(Error {
-                kind: errors::kind::ErrorKind::HTTPRequestError,
-                message: format!("read body with unknown err"),
-            }),
-        };
+        return Err(Error {
+            kind: errors::kind::ErrorKind::HTTPRequestError,
+            message: format!("get {} err: {}", url, result.err().unwrap()),
+        });
+    }
+
+    let response = result.unwrap();
+    let header = |name| match response.headers().get(name) {
+        Some(value) => value.to_str().ok().map(String::from),
+        None => None,
+    };
+    let usage = match header("subscription-userinfo") {
+        Some(value) => Usage::from_header(value.as_str()),
+        None => None,
+    };
+
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(Fetched {
+            response: None,
+            usage,
+        });
+    }
+    if !response.status().is_success() {
+        return Err(Error {
+            kind: errors::kind::ErrorKind::HTTPRequestError,
+            message: format!("get {} with status {}", url, response.status()),
+        });
     }
 
-    let body = result.unwrap();
-    let mut servers = vec![];
+    let content_type = header("content-type").unwrap_or_default();
+    let etag = header("etag");
+    let last_modified = header("last-modified");
 
-    let result = base64::decode(body);
+    let result = response.text().await;
     if result.is_err() {
         return Err(Error {
-            kind: errors::kind::ErrorKind::Base64DecodeError,
-            message: format!(
-                "decode subscription content base64 err: {}",
-                result.err().unwrap()
-            ),
+            kind: errors::kind::ErrorKind::HTTPRequestError,
+            message: format!("read body err: {}", result.err().unwrap()),
         });
     }
 
-    let bytes = result.unwrap();
-    let servers_data = match str::from_utf8(bytes.as_slice()) {
-        Ok(v) => v,
-        Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
-    };
+    Ok(Fetched {
+        response: Some(SubscriptionCache {
+            content_type,
+            etag,
+            last_modified,
+            body: result.unwrap(),
+        }),
+        usage,
+    })
+}
 
-    let server_entries = servers_data.split("\r\n");
-    for entry in server_entries {
-        if entry.trim().is_empty() {
-            continue;
+pub fn remove(ctx: &mut Context, name: &str) -> Result<(), Error> {
+    let subscription = ctx.settings.remove_subscription(name)?;
+
+    let count = ctx.servers.remove_group(subscription.name.as_str());
+    ctx.servers.save()?;
+    SubscriptionCache::remove(ctx.dir, subscription.name.as_str());
+
+    println!(
+        "subscription {} removed with {} servers",
+        subscription.name, count
+    );
+    Ok(())
+}
+
+pub fn list(ctx: &Context, json: bool) -> Result<(), Error> {
+    if json {
+        let subscriptions: Vec<serde_json::Value> = ctx
+            .settings
+            .subscriptions
+            .iter()
+            .map(|sub| {
+                serde_json::json!({
+                    "name": sub.name,
+                    "url": sub.url,
+                    "servers": ctx.servers.count_by_group(sub.name.as_str()),
+                    "added_at": sub.added_at,
+                    "last_updated_at": sub.last_updated_at,
+                    "skip_update": sub.skip_update,
+                    "usage": sub.usage,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::from(subscriptions));
+        return Ok(());
+    }
+
+    if ctx.settings.subscriptions.is_empty() {
+        println!("no subscriptions");
+        return Ok(());
+    }
+
+    let mut rows = vec![[
+        String::from("NAME"),
+        String::from("URL"),
+        String::from("SERVERS"),
+        String::from("ADDED AT"),
+        String::from("LAST UPDATED AT"),
+        String::from("SKIP UPDATE"),
+        String::from("USED / TOTAL"),
+        String::from("EXPIRE AT"),
+    ]];
+    for sub in ctx.settings.subscriptions.iter() {
+        rows.push([
+            sub.name.clone(),
+            sub.url.clone(),
+            ctx.servers.count_by_group(sub.name.as_str()).to_string(),
+            sub.added_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            match sub.last_updated_at.timestamp() {
+                0 => String::from("never"),
+                _ => sub.last_updated_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+            },
+            sub.skip_update.to_string(),
+            match &sub.usage {
+                Some(usage) if usage.total > 0 => {
+                    format!(
+                        "{} / {}",
+                        format_bytes(usage.used()),
+                        format_bytes(usage.total)
+                    )
+                }
+                Some(usage) => format_bytes(usage.used()),
+                None => String::from("-"),
+            },
+            match sub.usage.as_ref().and_then(|usage| usage.expire_at) {
+                Some(expire_at) => expire_at.format("%Y-%m-%d").to_string(),
+                None => String::from("-"),
+            },
+        ]);
+    }
+
+    let mut widths = [0; 8];
+    for row in rows.iter() {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count());
         }
+    }
 
-        match v2ray::server::from_str(entry) {
-            Ok(server) => servers.push(server),
-            Err(err) => {
-                println!(
-                    "parsing server entry: {} with error: {}",
-                    entry, err.message
-                );
-                continue;
-            }
+    for row in rows.iter() {
+        let line: Vec<String> = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:width$}", cell, width = widths[i]))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+
+    for sub in ctx.settings.subscriptions.iter() {
+        if let Some(warning) = sub.usage.as_ref().and_then(|usage| usage.warning()) {
+            println!("warning: subscription {} {}", sub.name, warning);
         }
     }
 
-    Ok(servers)
+    Ok(())
 }
 
-pub fn remove(ctx: &Context, name: &str) -> Result<(), Error> {
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Serves the canned responses one connection at a time and records
+    /// the request heads it received.
+    fn serve(responses: Vec<&'static str>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/sub", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let received = requests.clone();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(line.to_lowercase().as_str());
+                }
+                received.lock().unwrap().push(head);
+
+                if response.is_empty() {
+                    // never answer, let the client time out
+                    thread::sleep(Duration::from_secs(3));
+                    continue;
+                }
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn subscription(url: &str) -> Subscription {
+        Subscription {
+            name: String::from("test"),
+            url: String::from(url),
+            added_at: chrono::Local::now(),
+            last_updated_at: chrono::Local::now(),
+            last_checked_at: None,
+            skip_update: false,
+            update_interval: None,
+            usage: None,
+            via: None,
+            user_agent: None,
+            headers: BTreeMap::new(),
+            timeout: None,
+            filter: ServerFilter::default(),
+        }
+    }
+
+    fn options(retries: u32, timeout: u64) -> FetchOptions {
+        FetchOptions {
+            proxy: None,
+            timeout: Duration::from_secs(timeout),
+            retries,
+        }
+    }
+
+    const OK: &str = "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\netag: \"v1\"\r\ncontent-length: 5\r\nconnection: close\r\n\r\nhello";
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+    const NOT_MODIFIED: &str = "HTTP/1.1 304 Not Modified\r\nconnection: close\r\n\r\n";
+    const LINK: &str = "trojan://p@a.example.com:443#A";
+    const LINK_V2: &str = "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\netag: \"v2\"\r\ncontent-length: 30\r\nconnection: close\r\n\r\ntrojan://p@a.example.com:443#A";
+
+    fn workdir(name: &str) -> (Dir, Servers) {
+        let path = std::env::temp_dir().join(format!("v2up-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("servers.yaml"), "servers: []\n").unwrap();
+
+        let workdir = Dir::new(String::from(path.to_str().unwrap())).unwrap();
+        let servers = Servers::from_workdir(&workdir).unwrap();
+        (workdir, servers)
+    }
+
+    fn cached(etag: &str) -> SubscriptionCache {
+        SubscriptionCache {
+            content_type: String::from("text/plain"),
+            etag: Some(String::from(etag)),
+            last_modified: None,
+            body: String::from(LINK),
+        }
+    }
+
+    #[test]
+    fn fetch_retries_transient_errors() {
+        let (url, requests) = serve(vec![UNAVAILABLE, OK]);
+        let runtime = Runtime::new().unwrap();
+
+        let fetched = runtime
+            .block_on(fetch(&subscription(&url), None, &options(2, 5)))
+            .unwrap();
+        let response = fetched.response.unwrap();
+        assert_eq!(response.body, "hello");
+        assert_eq!(response.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn fetch_fails_without_retries() {
+        let (url, requests) = serve(vec![UNAVAILABLE]);
+        let runtime = Runtime::new().unwrap();
+
+        let result = runtime.block_on(fetch(&subscription(&url), None, &options(0, 5)));
+        assert!(result.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn fetch_times_out() {
+        let (url, _) = serve(vec![""]);
+        let runtime = Runtime::new().unwrap();
+
+        let result = runtime.block_on(fetch(&subscription(&url), None, &options(0, 1)));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fetch_sends_conditional_request_and_headers() {
+        let (url, requests) = serve(vec![NOT_MODIFIED]);
+        let runtime = Runtime::new().unwrap();
+
+        let mut sub = subscription(&url);
+        sub.user_agent = Some(String::from("clash"));
+        sub.headers
+            .insert(String::from("Authorization"), String::from("Bearer t"));
+        let cache = SubscriptionCache {
+            content_type: String::from("text/plain"),
+            etag: Some(String::from("\"v1\"")),
+            last_modified: None,
+            body: String::from("hello"),
+        };
+
+        let fetched = runtime
+            .block_on(fetch(&sub, Some(&cache), &options(0, 5)))
+            .unwrap();
+        assert!(fetched.response.is_none());
+
+        let head = requests.lock().unwrap()[0].clone();
+        assert!(head.contains("if-none-match: \"v1\""));
+        assert!(head.contains("user-agent: clash"));
+        assert!(head.contains("authorization: bearer t"));
+    }
+
+    #[test]
+    fn fetch_all_keeps_job_order() {
+        let (slow, _) = serve(vec![""]);
+        let (fast, _) = serve(vec![OK]);
+        let runtime = Runtime::new().unwrap();
+
+        let jobs = vec![
+            FetchJob {
+                sub: subscription(&slow),
+                cache: None,
+                options: options(0, 1),
+            },
+            FetchJob {
+                sub: subscription(&fast),
+                cache: None,
+                options: options(0, 5),
+            },
+        ];
+        let results = fetch_all(&runtime, jobs, 2);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert_eq!(
+            results[1].as_ref().unwrap().response.as_ref().unwrap().body,
+            "hello"
+        );
+    }
+
+    #[test]
+    fn update_falls_back_to_cache() {
+        let (workdir, mut servers) = workdir("fallback");
+        let mut sub = subscription("http://127.0.0.1:1/sub");
+        let failed = Err(Error {
+            kind: errors::kind::ErrorKind::HTTPRequestError,
+            message: String::from("connection refused"),
+        });
+
+        let result = update_subscription(
+            &workdir,
+            &mut servers,
+            &mut sub,
+            Some(cached("\"v1\"")),
+            failed,
+            false,
+        );
+        match result {
+            UpdateResult::Failed(err) => assert!(err.ends_with("used cached copy")),
+            _ => panic!("expect failed result"),
+        }
+        assert_eq!(servers.count_by_group("test"), 1);
+        assert!(SubscriptionCache::load(&workdir, "test").is_none());
+    }
+
+    #[test]
+    fn update_reuses_cache_when_not_modified() {
+        let (url, _) = serve(vec![NOT_MODIFIED]);
+        let (workdir, mut servers) = workdir("not-modified");
+        let runtime = Runtime::new().unwrap();
+        let mut sub = subscription(&url);
+        let cache = cached("\"v1\"");
+
+        let fetched = runtime.block_on(fetch(&sub, Some(&cache), &options(0, 5)));
+        let result = update_subscription(
+            &workdir,
+            &mut servers,
+            &mut sub,
+            Some(cache),
+            fetched,
+            false,
+        );
+        assert!(matches!(result, UpdateResult::Updated(1)));
+        assert_eq!(servers.count_by_group("test"), 1);
+        // nothing new was fetched, the cache is not written
+        assert!(SubscriptionCache::load(&workdir, "test").is_none());
+    }
+
+    #[test]
+    fn update_saves_new_content_and_validators() {
+        let (url, _) = serve(vec![LINK_V2, LINK_V2]);
+        let (workdir, mut servers) = workdir("save-cache");
+        let runtime = Runtime::new().unwrap();
+        let mut sub = subscription(&url);
+
+        let fetched = runtime.block_on(fetch(&sub, None, &options(0, 5)));
+        let result = update_subscription(&workdir, &mut servers, &mut sub, None, fetched, false);
+        assert!(matches!(result, UpdateResult::Updated(1)));
+        assert_eq!(
+            SubscriptionCache::load(&workdir, "test"),
+            Some(cached("\"v2\""))
+        );
+
+        // the same body with other validators only refreshes the cache
+        SubscriptionCache::remove(&workdir, "test");
+        let cache = cached("\"v1\"");
+        let fetched = runtime.block_on(fetch(&sub, Some(&cache), &options(0, 5)));
+        let result = update_subscription(
+            &workdir,
+            &mut servers,
+            &mut sub,
+            Some(cache),
+            fetched,
+            false,
+        );
+        assert!(matches!(result, UpdateResult::Unchanged));
+        assert_eq!(
+            SubscriptionCache::load(&workdir, "test"),
+            Some(cached("\"v2\""))
+        );
+    }
 }
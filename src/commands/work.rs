@@ -1,13 +1,29 @@
+use crate::commands::servers;
+use crate::commands::subscriptions;
 use crate::context::Context;
 use crate::errors::kind::ErrorKind::GetCurrentProcessIDError;
 use crate::errors::Error;
 use crate::server;
+use crate::utils::logger::Logger;
 use crate::utils::pid_file::{read_pidfile, write_pidfile};
+use crate::utils::process::Process;
+use crate::v2ray::config::Config;
+use crate::v2ray::config::Strategy;
+use crate::v2ray::server::ServerIdentity;
+use crate::workdir::dir::Dir;
+use crate::workdir::latency::Latency;
+use crate::workdir::lock::WorkdirLock;
+use crate::workdir::selection::Selection;
+use crate::workdir::servers::Server;
+use crate::workdir::servers::Servers;
+use crate::workdir::settings::Settings;
 
 use libc::c_int;
 use signal_hook::consts::signal::*;
 use signal_hook::iterator::Signals;
+use std::process::Command;
 use std::thread;
+use std::time::{Duration, Instant};
 
 use sysinfo::{get_current_pid, SystemExt};
 
@@ -38,6 +54,10 @@ pub fn exec(ctx: &Context) -> Result<(), Error> {
         server::run();
     });
 
+    // refresh subscriptions in the background
+    let workdir = String::from(ctx.dir.path());
+    thread::spawn(move || schedule_updates(workdir));
+
     const SIGNALS: &[c_int] = &[
         SIGTERM, SIGQUIT, SIGINT, SIGTSTP, SIGWINCH, SIGHUP, SIGCHLD, SIGCONT,
     ];
@@ -50,6 +70,300 @@ pub fn exec(ctx: &Context) -> Result<(), Error> {
     Ok(())
 }
 
+/// How often the worker looks for subscriptions due for an update
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(60);
+
+fn schedule_updates(workdir: String) {
+    let mut last_check: Option<Instant> = None;
+    loop {
+        thread::sleep(SCHEDULE_INTERVAL);
+        run_scheduled(workdir.as_str(), &mut last_check);
+    }
+}
+
+/// Refreshes the subscriptions whose update interval has passed and checks
+/// the selected server. Subscriptions are fetched without the workdir lock
+/// and merged into the workdir loaded again under it, as the cli may have
+/// changed it meanwhile.
+fn run_scheduled(path: &str, last_check: &mut Option<Instant>) {
+    let pending = with_context(path, false, |ctx, logger| {
+        subscriptions::fetch_due(ctx).unwrap_or_else(|err| {
+            logger.error(format!("update subscriptions err: {}", err).as_str());
+            vec![]
+        })
+    });
+
+    if let Some(pending) = pending.filter(|pending| !pending.is_empty()) {
+        with_context(path, true, |ctx, logger| {
+            match subscriptions::merge_due(ctx, pending) {
+                Ok((results, reapplied)) => {
+                    for (name, result) in results.iter() {
+                        logger.info(format!("subscription {}: {}", name, result).as_str());
+                    }
+                    if reapplied {
+                        logger.info("selected server changed, v2ray.json rewritten");
+                    }
+                }
+                Err(err) => logger.error(format!("update subscriptions err: {}", err).as_str()),
+            }
+        });
+    }
+
+    // servers are measured without the lock too
+    let plan = with_context(path, false, |ctx, logger| {
+        plan_switch(ctx, logger, last_check, Instant::now()).unwrap_or_else(|err| {
+            logger.error(format!("auto select server err: {}", err).as_str());
+            None
+        })
+    });
+    if let Some(plan) = plan.flatten() {
+        let applied = with_context(path, true, |ctx, logger| {
+            if let Err(err) = apply_switch(ctx, logger, plan) {
+                logger.error(format!("auto select server err: {}", err).as_str());
+            }
+        });
+        if applied.is_none() {
+            // check again on the next tick instead of after the interval
+            *last_check = None;
+        }
+    }
+}
+
+/// Loads the workdir and runs `f` with it, holding the workdir lock with
+/// `locked`. Returns None when the workdir cannot be loaded or another
+/// process holds the lock.
+fn with_context<T>(
+    path: &str,
+    locked: bool,
+    f: impl FnOnce(&mut Context, &Logger) -> T,
+) -> Option<T> {
+    let workdir = match Dir::new(String::from(path)) {
+        Ok(workdir) => workdir,
+        Err(err) => {
+            println!("load workdir {} err: {}", path, err);
+            return None;
+        }
+    };
+    // the cli holds the lock while it prompts or measures servers, the
+    // worker does not wait for it and tries again on the next tick
+    let _lock = match locked {
+        true => match WorkdirLock::try_acquire(&workdir) {
+            Ok(Some(lock)) => Some(lock),
+            Ok(None) => {
+                println!("workdir {} is locked, retry on the next tick", path);
+                return None;
+            }
+            Err(err) => {
+                println!("{}", err);
+                return None;
+            }
+        },
+        false => None,
+    };
+    let mut settings = match Settings::from_workdir(&workdir) {
+        Ok(settings) => settings,
+        Err(err) => {
+            println!("load settings err: {}", err);
+            return None;
+        }
+    };
+    let logger = Logger::new(settings.log.location.as_str());
+
+    let mut servers = match Servers::from_workdir(&workdir) {
+        Ok(servers) => servers,
+        Err(err) => {
+            logger.error(format!("load servers err: {}", err).as_str());
+            return None;
+        }
+    };
+    let mut config = match Config::load(workdir.filepath("v2ray.json").as_str()) {
+        Ok(config) => config,
+        Err(err) => {
+            logger.error(format!("load v2ray config err: {}", err).as_str());
+            return None;
+        }
+    };
+
+    let mut v2ray_cmd = Command::new(settings.v2ray.bin.as_str());
+    v2ray_cmd.args(["-config", workdir.filepath("v2ray.json").as_str(), "&"]);
+    let mut v2ray_process = Process::new(&mut v2ray_cmd, workdir.filepath("v2ray.pid").as_str());
+    let mut worker_cmd = Command::new("v2up");
+    worker_cmd.args(["work"]);
+    let mut worker_process = Process::new(&mut worker_cmd, workdir.filepath("worker.pid").as_str());
+
+    let mut ctx = Context {
+        dir: &workdir,
+        settings: &mut settings,
+        servers: &mut servers,
+        config: &mut config,
+        v2ray_process: &mut v2ray_process,
+        worker_process: &mut worker_process,
+    };
+    Some(f(&mut ctx, &logger))
+}
+
+/// The outcome of a health check, worked out while the workdir was unlocked
+struct SwitchPlan {
+    /// the selection that was checked, nothing is switched when the cli
+    /// changed it meanwhile
+    selection: Selection,
+    latencies: Vec<Latency>,
+    /// the servers to use instead, when some selected ones failed
+    switch_to: Option<Vec<Server>>,
+}
+
+/// Probes the selected servers every `auto_select.interval`. Failing
+/// balanced servers are dropped while others still work, otherwise the
+/// other servers are measured for the fastest healthy one.
+fn plan_switch(
+    ctx: &Context,
+    logger: &Logger,
+    last_check: &mut Option<Instant>,
+    now: Instant,
+) -> Result<Option<SwitchPlan>, Error> {
+    let auto_select = match &ctx.settings.auto_select {
+        Some(auto_select) => auto_select,
+        None => return Ok(None),
+    };
+    let interval = Duration::from_secs(auto_select.interval.seconds);
+    if !is_check_due(*last_check, now, interval) {
+        return Ok(None);
+    }
+    *last_check = Some(now);
+
+    let selection = match Selection::load(ctx.dir) {
+        Some(selection) if needs_health_check(&selection) => selection,
+        _ => return Ok(None),
+    };
+
+    let real = auto_select.real;
+    let members: Vec<Server> = selection
+        .members()
+        .into_iter()
+        .filter_map(|member| member.locate(ctx.servers).cloned())
+        .collect();
+    let mut latencies = servers::measure_candidates(ctx, &members, real)?;
+    let (healthy, failed) = split_healthy(members, &latencies, real);
+    for server in failed.iter() {
+        logger.info(
+            format!(
+                "selected server {} failed health probe",
+                server.server.name()
+            )
+            .as_str(),
+        );
+    }
+
+    let switch_to = match (healthy.is_empty(), failed.is_empty()) {
+        (false, true) => None,
+        (false, false) => Some(healthy),
+        (true, _) => {
+            // failed servers are not measured again, they could be picked otherwise
+            let failed: Vec<ServerIdentity> = failed.iter().map(|s| s.server.identity()).collect();
+            let candidates: Vec<Server> = servers::filter_servers(
+                ctx.servers,
+                auto_select.group.as_deref(),
+                auto_select.pattern.as_deref(),
+            )?
+            .into_iter()
+            .filter(|s| !failed.contains(&s.server.identity()))
+            .cloned()
+            .collect();
+            let results = servers::measure_candidates(ctx, &candidates, real)?;
+            latencies.extend(results.iter().cloned());
+
+            match servers::rank(candidates, results.into_iter().map(Some).collect(), real) {
+                Some((server, ms)) => {
+                    logger.info(
+                        format!(
+                            "fastest healthy server {} ({} ms)",
+                            server.server.name(),
+                            ms
+                        )
+                        .as_str(),
+                    );
+                    Some(vec![server])
+                }
+                None => {
+                    logger.error("no healthy server to switch to");
+                    None
+                }
+            }
+        }
+    };
+
+    Ok(Some(SwitchPlan {
+        selection,
+        latencies,
+        switch_to,
+    }))
+}
+
+/// leastPing balancers leave out failing servers by themselves, random
+/// ones keep sending connections to them
+fn needs_health_check(selection: &Selection) -> bool {
+    selection.strategy != Some(Strategy::LeastPing)
+}
+
+/// Whether `interval` has passed since the last health check
+fn is_check_due(last_check: Option<Instant>, now: Instant, interval: Duration) -> bool {
+    last_check.is_none_or(|last| now.saturating_duration_since(last) >= interval)
+}
+
+/// Splits the measured servers into the healthy and the failed ones
+fn split_healthy(
+    servers: Vec<Server>,
+    latencies: &[Latency],
+    real: bool,
+) -> (Vec<Server>, Vec<Server>) {
+    let mut healthy = vec![];
+    let mut failed = vec![];
+    for (server, latency) in servers.into_iter().zip(latencies) {
+        match servers::ranked_ms(latency, real) {
+            Some(_) => healthy.push(server),
+            None => failed.push(server),
+        }
+    }
+    (healthy, failed)
+}
+
+/// Keeps the measured latencies and switches servers, in the workdir loaded
+/// again under the lock
+fn apply_switch(ctx: &mut Context, logger: &Logger, plan: SwitchPlan) -> Result<(), Error> {
+    servers::record_latencies(ctx.dir, &plan.latencies)?;
+
+    let switch_to = match plan.switch_to {
+        Some(switch_to) => switch_to,
+        None => return Ok(()),
+    };
+    if Selection::load(ctx.dir).as_ref() != Some(&plan.selection) {
+        logger.info("selected server changed while measuring, not switching");
+        return Ok(());
+    }
+    let gone = switch_to.iter().find(|server| {
+        !ctx.servers
+            .iter()
+            .any(|s| s.group == server.group && s.server == server.server)
+    });
+    if let Some(server) = gone {
+        logger.info(format!("server {} is gone, not switching", server.server.name()).as_str());
+        return Ok(());
+    }
+
+    let strategy = plan.selection.strategy.unwrap_or_default();
+    servers::use_servers(ctx, &switch_to, strategy, false)?;
+
+    let names: Vec<&str> = switch_to.iter().map(|s| s.server.name()).collect();
+    logger.info(
+        format!(
+            "switched to {}, v2ray restarted if running",
+            names.join(", ")
+        )
+        .as_str(),
+    );
+    Ok(())
+}
+
 /// Finds out if v2up worker process is running or not
 ///
 /// check two things:
@@ -77,3 +391,101 @@ fn worker_already_running() -> bool {
         None => false,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2ray::server::from_str;
+
+    #[test]
+    fn health_check_waits_for_interval() {
+        let interval = Duration::from_secs(600);
+        let start = Instant::now();
+
+        assert!(is_check_due(None, start, interval));
+        assert!(!is_check_due(Some(start), start, interval));
+        assert!(!is_check_due(
+            Some(start),
+            start + Duration::from_secs(599),
+            interval
+        ));
+        assert!(is_check_due(Some(start), start + interval, interval));
+        // a clock going backwards does not trigger a check
+        assert!(!is_check_due(Some(start + interval), start, interval));
+    }
+
+    #[test]
+    fn split_healthy_servers() {
+        let servers: Vec<Server> = ["A", "B", "C"]
+            .iter()
+            .map(|name| Server {
+                group: String::from("g"),
+                server: from_str(format!("trojan://p@{}.example.com:443#{}", name, name).as_str())
+                    .unwrap(),
+            })
+            .collect();
+        let latencies: Vec<Latency> = servers
+            .iter()
+            .zip([Some(30), None, Some(10)])
+            .map(|(server, tcp)| {
+                let mut latency = Latency::new(server.server.identity());
+                latency.tcp = tcp;
+                latency.error = tcp.is_none().then(|| String::from("timeout"));
+                latency
+            })
+            .collect();
+
+        let (healthy, failed) = split_healthy(servers.clone(), &latencies, false);
+        let names = |servers: &[Server]| -> Vec<String> {
+            servers
+                .iter()
+                .map(|s| String::from(s.server.name()))
+                .collect()
+        };
+        assert_eq!(names(&healthy), vec!["A", "C"]);
+        assert_eq!(names(&failed), vec!["B"]);
+
+        // without delays nothing counts as healthy when ranking by delay
+        let (healthy, failed) = split_healthy(servers, &latencies, true);
+        assert!(healthy.is_empty());
+        assert_eq!(failed.len(), 3);
+    }
+
+    #[test]
+    fn nothing_to_check_without_selection() {
+        let path = std::env::temp_dir().join(format!("v2up-work-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let path = String::from(path.to_str().unwrap());
+        let workdir = Dir::new(path.clone()).unwrap();
+        workdir.init().unwrap();
+
+        std::fs::write(
+            workdir.filepath("settings.yaml"),
+            format!(
+                "v2ray:\n  bin: ''\nlog:\n  location: {}\nsubscriptions: []\nauto_select:\n  interval: 10m\n",
+                workdir.filepath("v2up.log")
+            ),
+        )
+        .unwrap();
+        std::fs::write(workdir.filepath("servers.yaml"), "servers: []\n").unwrap();
+        let mut servers = Servers::from_workdir(&workdir).unwrap();
+        servers
+            .update_by_group_name(
+                "g",
+                &vec![from_str("trojan://p@127.0.0.1:1#A").unwrap()],
+                &Default::default(),
+                false,
+            )
+            .unwrap();
+        servers.save().unwrap();
+
+        let mut last_check = None;
+        let plan = with_context(path.as_str(), false, |ctx, logger| {
+            plan_switch(ctx, logger, &mut last_check, Instant::now())
+        });
+        assert!(matches!(plan, Some(Ok(None))));
+        assert!(last_check.is_some());
+
+        let _ = std::fs::remove_dir_all(workdir.path());
+    }
+}
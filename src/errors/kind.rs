@@ -9,14 +9,26 @@ pub enum ErrorKind {
     WriteFileError,
     EncodeYAMLError,
     ParseJSONError,
+    ParseYAMLError,
     HTTPRequestError,
     Base64DecodeError,
     UnknownServerProtocol,
+    ParseServerURLError,
+    UnknownSubscriptionFormat,
+    ServerNotFound,
+    AmbiguousServer,
+    SubscriptionNotFound,
+    SubscriptionAlreadyExists,
+    InvalidProxy,
+    InvalidRegex,
+    InvalidInterval,
+    LatencyTestError,
     GetCurrentProcessIDError,
     ExecuteCommandError,
     TemplateNotFound,
     RenderTemplateNotFound,
     InvalidPath,
+    LockWorkdirError,
 }
 
 impl fmt::Display for ErrorKind {
@@ -28,7 +28,10 @@ mod v2ray;
 #[derive(Subcommand)]
 enum Commands {
     /// does testing things
-    Servers {},
+    Servers {
+        #[clap(subcommand)]
+        command: Option<servers::Commands>,
+    },
     Status {},
     Work {},
     Start {},
@@ -92,6 +95,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         },
     };
 
+    // the worker changes the workdir in the background too, hold the lock
+    // from loading to saving
+    let _lock = match &cli.command {
+        Some(Commands::Servers { .. }) | Some(Commands::Subscriptions { .. }) => {
+            match workdir::lock::WorkdirLock::acquire(&workdir) {
+                Ok(lock) => Some(lock),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1)
+                }
+            }
+        }
+        _ => None,
+    };
+
     // workdir OK, we could load all the settings &
     // load settings
     let mut settings = Settings::from_workdir(&workdir).expect("err loading settings");
@@ -126,8 +144,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
 
     match &cli.command {
-        Some(Commands::Servers {}) => {
-            servers::exec(&mut ctx);
+        Some(Commands::Servers { command }) => {
+            if let Err(err) = servers::exec(&mut ctx, command) {
+                eprintln!("servers err: {}", err);
+                std::process::exit(1)
+            }
         }
         Some(Commands::Status {}) => {
             status::exec(&ctx);
@@ -148,7 +169,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             println!("v2up version {}", env!("CARGO_PKG_VERSION"))
         }
         Some(Commands::Subscriptions { command }) => {
-            subscriptions::exec(&mut ctx, command).unwrap();
+            if let Err(err) = subscriptions::exec(&mut ctx, command) {
+                eprintln!("subscriptions err: {}", err);
+                std::process::exit(1)
+            }
         }
         None => {}
     }
@@ -1,7 +1,41 @@
-enum Commands {
-    Debug,
+use std::fs::OpenOptions;
+use std::io::Write;
+
+/// Appends timestamped lines to the v2up log, `log.location` in settings.yaml
+pub struct Logger {
+    location: String,
 }
 
-pub fn println() {
+impl Logger {
+    pub fn new(location: &str) -> Logger {
+        Logger {
+            location: String::from(location),
+        }
+    }
+
+    pub fn info(&self, message: &str) {
+        self.write("INFO", message);
+    }
+
+    pub fn error(&self, message: &str) {
+        self.write("ERROR", message);
+    }
 
-}
\ No newline at end of file
+    fn write(&self, level: &str, message: &str) {
+        let line = format!(
+            "{} [{}] {}\n",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            level,
+            message
+        );
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.location.as_str())
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(err) = result {
+            println!("write log {} err: {}", self.location, err);
+        }
+    }
+}
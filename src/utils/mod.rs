@@ -1,3 +1,5 @@
 pub mod logger;
 pub mod pid_file;
+pub mod ping;
 pub mod process;
+pub mod runtime;
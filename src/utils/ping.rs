@@ -0,0 +1,87 @@
+use crate::errors::kind::ErrorKind;
+use crate::errors::Error;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_native_tls::native_tls;
+use tokio_native_tls::TlsConnector;
+
+/// Connects to the address, the returned duration leaves out the DNS lookup
+pub async fn tcp_connect(
+    address: &str,
+    port: u16,
+    limit: Duration,
+) -> Result<(TcpStream, Duration), Error> {
+    let addr = match timeout(limit, tokio::net::lookup_host((address, port))).await {
+        Ok(Ok(mut addrs)) => match addrs.next() {
+            Some(addr) => addr,
+            None => return Err(error(format!("resolve {}: no address", address))),
+        },
+        Ok(Err(err)) => return Err(error(format!("resolve {}: {}", address, err))),
+        Err(_) => return Err(error(format!("resolve {}: timeout", address))),
+    };
+
+    let start = Instant::now();
+    match timeout(limit, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => Ok((stream, start.elapsed())),
+        Ok(Err(err)) => Err(error(format!("connect {}: {}", addr, err))),
+        Err(_) => Err(error(format!("connect {}: timeout", addr))),
+    }
+}
+
+/// Runs a TLS handshake over the stream. Certificates are not verified,
+/// only the time it takes matters.
+pub async fn tls_handshake(
+    stream: TcpStream,
+    server_name: &str,
+    limit: Duration,
+) -> Result<Duration, Error> {
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build();
+    let connector = match connector {
+        Ok(connector) => TlsConnector::from(connector),
+        Err(err) => return Err(error(format!("create tls connector: {}", err))),
+    };
+
+    let start = Instant::now();
+    match timeout(limit, connector.connect(server_name, stream)).await {
+        Ok(Ok(_)) => Ok(start.elapsed()),
+        Ok(Err(err)) => Err(error(format!(
+            "tls handshake with {}: {}",
+            server_name, err
+        ))),
+        Err(_) => Err(error(format!(
+            "tls handshake with {}: timeout",
+            server_name
+        ))),
+    }
+}
+
+fn error(message: String) -> Error {
+    Error {
+        kind: ErrorKind::LatencyTestError,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::runtime;
+
+    #[test]
+    fn tcp_connect_measures_local_listener() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let runtime = runtime::new().unwrap();
+
+        let result = runtime.block_on(tcp_connect("127.0.0.1", port, Duration::from_secs(1)));
+        assert!(result.unwrap().1 < Duration::from_secs(1));
+
+        drop(listener);
+        let result = runtime.block_on(tcp_connect("127.0.0.1", port, Duration::from_secs(1)));
+        assert!(result.is_err());
+    }
+}
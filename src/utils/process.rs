@@ -67,6 +67,11 @@ impl<'a> Process<'a> {
     }
 
     pub fn stop(&mut self) -> Result<(), Error> {
+        // pid 0 would signal the whole process group
+        if self.pid == 0 {
+            return Ok(());
+        }
+
         let result = nix::sys::signal::kill(Pid::from_raw(self.pid), Signal::SIGTERM);
         match result {
             Ok(_) => Ok(()),
@@ -0,0 +1,14 @@
+use crate::errors::kind::ErrorKind;
+use crate::errors::Error;
+use tokio::runtime::Runtime;
+
+/// Creates the tokio runtime async work of a command runs on
+pub fn new() -> Result<Runtime, Error> {
+    match Runtime::new() {
+        Ok(runtime) => Ok(runtime),
+        Err(err) => Err(Error {
+            kind: ErrorKind::ExecuteCommandError,
+            message: format!("create tokio runtime err: {}", err),
+        }),
+    }
+}
@@ -19,6 +19,8 @@ pub struct Config {
     pub dns: Dns,
     pub routing: Routing,
     pub transport: Transport,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub observatory: Option<Observatory>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -70,6 +72,18 @@ pub struct StreamSettings {
     pub security: String,
     pub tls_settings: Option<TLSSettings>,
     pub ws_settings: Option<WSSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_settings: Option<TcpSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http_settings: Option<HttpSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kcp_settings: Option<KcpSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quic_settings: Option<QuicSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc_settings: Option<GrpcSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reality_settings: Option<RealitySettings>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -78,6 +92,10 @@ pub struct TLSSettings {
     pub server_name: String,
     pub allow_insecure_ciphers: bool,
     pub allow_insecure: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -93,6 +111,46 @@ pub struct WSSettingsHeaders {
     pub host: String,
 }
 
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HttpSettings {
+    #[serde(skip_serializing_if = "<[_]>::is_empty", default)]
+    pub host: Vec<String>,
+    pub path: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KcpSettings {
+    pub header: Header,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuicSettings {
+    pub security: String,
+    pub key: String,
+    pub header: Header,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrpcSettings {
+    pub service_name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RealitySettings {
+    pub server_name: String,
+    pub fingerprint: String,
+    pub public_key: String,
+    pub short_id: String,
+    pub spider_x: String,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TcpSettings {
@@ -104,12 +162,14 @@ pub struct TcpSettings {
 pub struct Header {
     #[serde(rename = "type")]
     pub type_field: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<Value>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct OutboundSettings {
     pub vnext: Option<Vec<Vnext>>,
-    pub servers: Option<Vec<ServerTrojan>>,
+    pub servers: Option<Vec<ServerAddressPassword>>,
 }
 
 impl OutboundSettings {
@@ -135,15 +195,19 @@ pub struct SettingsVmess {
 #[serde(rename_all = "camelCase")]
 pub struct SettingsTrojan {
     #[serde(default)]
-    pub servers: Vec<ServerTrojan>,
+    pub servers: Vec<ServerAddressPassword>,
 }
 
+/// An entry of `settings.servers`, shared by trojan and shadowsocks outbounds
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct ServerTrojan {
+pub struct ServerAddressPassword {
     pub address: String,
     pub port: i32,
     pub password: String,
+    /// cipher of a shadowsocks server, trojan servers leave it empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -159,9 +223,15 @@ pub struct Vnext {
 #[serde(rename_all = "camelCase")]
 pub struct User {
     pub id: String,
-    pub alter_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alter_id: Option<i32>,
     // pub level: i32,
-    pub security: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub security: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow: Option<String>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -179,6 +249,49 @@ pub struct Dns {}
 #[serde(rename_all = "camelCase")]
 pub struct Routing {
     pub settings: Settings3,
+    #[serde(default, skip_serializing_if = "<[_]>::is_empty")]
+    pub balancers: Vec<Balancer>,
+}
+
+/// Spreads the traffic routed to `tag` over the outbounds whose tags start
+/// with one of the selectors
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Balancer {
+    pub tag: String,
+    pub selector: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<BalancerStrategy>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalancerStrategy {
+    #[serde(rename = "type")]
+    pub type_field: Strategy,
+}
+
+/// How a balancer picks an outbound
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum Strategy {
+    /// any outbound, at random, failing ones included
+    Random,
+    /// the outbound with the lowest delay measured by the observatory,
+    /// failing ones are left out
+    #[default]
+    LeastPing,
+}
+
+/// Probes the outbounds whose tags start with one of the selectors, so that
+/// balancers can leave out the ones that fail
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Observatory {
+    pub subject_selector: Vec<String>,
+    #[serde(rename = "probeURL")]
+    pub probe_url: String,
+    pub probe_interval: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -193,18 +306,109 @@ pub struct Settings3 {
 pub struct Transport {}
 
 impl Config {
+    /// Returns the url of the local http inbound, or of the socks inbound
+    /// when there is no http inbound
+    pub fn local_proxy_url(&self) -> Option<String> {
+        let url = |inbound: &Inbound, scheme: &str| {
+            let host = match inbound.listen.as_str() {
+                "" | "0.0.0.0" => "127.0.0.1",
+                "::" => "[::1]",
+                listen => listen,
+            };
+            format!("{}://{}:{}", scheme, host, inbound.port)
+        };
+
+        let inbounds = self.inbounds.iter();
+        match inbounds.clone().find(|inbound| inbound.protocol == "http") {
+            Some(inbound) => Some(url(inbound, "http")),
+            None => inbounds
+                .clone()
+                .find(|inbound| inbound.protocol == "socks")
+                .map(|inbound| url(inbound, "socks5h")),
+        }
+    }
+
     pub fn use_server(&mut self, server: &ServerType) -> Result<(), Error> {
-        for outbound in &mut self.outbounds {
-            if !outbound.tag.eq("proxy") {
-                continue;
+        self.use_servers(std::slice::from_ref(server), Strategy::default(), "")
+    }
+
+    /// Points the proxy traffic at the servers. A single server becomes the
+    /// `proxy` outbound, several become `proxy-0`, `proxy-1`... behind a
+    /// balancer tagged `proxy`. With `LeastPing` an observatory probes
+    /// `probe_url` through each of them.
+    pub fn use_servers(
+        &mut self,
+        servers: &[ServerType],
+        strategy: Strategy,
+        probe_url: &str,
+    ) -> Result<(), Error> {
+        if servers.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::ServerNotFound,
+                message: String::from("no servers to use"),
+            });
+        }
+
+        let is_proxy = |tag: &str| tag == PROXY_TAG || tag.starts_with(BALANCED_PREFIX);
+        let position = self
+            .outbounds
+            .iter()
+            .position(|outbound| is_proxy(&outbound.tag))
+            .unwrap_or(0);
+        self.outbounds.retain(|outbound| !is_proxy(&outbound.tag));
+
+        let balanced = servers.len() > 1;
+        for (i, server) in servers.iter().enumerate().rev() {
+            let mut outbound = server.to_outbound();
+            if balanced {
+                outbound.tag = format!("{}{}", BALANCED_PREFIX, i);
             }
+            self.outbounds.insert(position, outbound);
+        }
 
-            let o = server.to_outbound();
-            outbound.mux = o.mux;
-            outbound.settings = o.settings;
-            outbound.stream_settings = o.stream_settings;
-            outbound.protocol = o.protocol;
-            outbound.tag = o.tag;
+        self.routing
+            .settings
+            .rules
+            .retain(|rule| rule != &balancer_rule());
+
+        // rules sending traffic to the proxy follow it between the outbound
+        // and the balancer, which share the tag
+        let (from, to) = match balanced {
+            true => ("outboundTag", "balancerTag"),
+            false => ("balancerTag", "outboundTag"),
+        };
+        for rule in self.routing.settings.rules.iter_mut() {
+            if let Some(rule) = rule.as_object_mut() {
+                if rule.get(from).is_some_and(|tag| tag == PROXY_TAG) {
+                    rule.remove(from);
+                    rule.insert(String::from(to), json!(PROXY_TAG));
+                }
+            }
+        }
+        self.routing
+            .balancers
+            .retain(|balancer| balancer.tag != PROXY_TAG);
+        self.observatory = None;
+
+        if balanced {
+            // v2ray sends unmatched traffic to the first outbound, which is
+            // no longer the only proxy
+            self.routing.settings.rules.push(balancer_rule());
+            self.routing.balancers.push(Balancer {
+                tag: String::from(PROXY_TAG),
+                selector: vec![String::from(BALANCED_PREFIX)],
+                strategy: Some(BalancerStrategy {
+                    type_field: strategy,
+                }),
+            });
+        }
+        // only leastPing reads the probe results
+        if balanced && strategy == Strategy::LeastPing {
+            self.observatory = Some(Observatory {
+                subject_selector: vec![String::from(BALANCED_PREFIX)],
+                probe_url: String::from(probe_url),
+                probe_interval: String::from(PROBE_INTERVAL),
+            });
         }
         self.save()
     }
@@ -250,6 +454,18 @@ impl Config {
     }
 }
 
+/// Tag of the proxy outbound, or of the balancer when several servers are used
+const PROXY_TAG: &str = "proxy";
+/// Tag prefix of the outbounds behind the balancer
+const BALANCED_PREFIX: &str = "proxy-";
+/// How often the observatory probes the balanced outbounds
+const PROBE_INTERVAL: &str = "1m";
+
+/// Routes everything not matched by an earlier rule to the balancer
+fn balancer_rule() -> Value {
+    json!({"type": "field", "network": "tcp,udp", "balancerTag": PROXY_TAG})
+}
+
 const INITIAL_CONFIG: &str = r#"{
     "log": {
       "error": "",
@@ -336,3 +552,99 @@ const INITIAL_CONFIG: &str = r#"{
     },
     "transport": {}
   }"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2ray::server::from_str;
+
+    fn config(name: &str) -> Config {
+        let mut config: Config = serde_json::from_str(INITIAL_CONFIG).unwrap();
+        config.filepath = std::env::temp_dir()
+            .join(format!("v2up-{}-{}.json", name, std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        config.routing.settings.rules =
+            vec![json!({"type": "field", "domain": ["example.com"], "outboundTag": "proxy"})];
+        config
+    }
+
+    fn tags(config: &Config) -> Vec<&str> {
+        config.outbounds.iter().map(|o| o.tag.as_str()).collect()
+    }
+
+    #[test]
+    fn balance_across_servers() {
+        let hk = from_str("trojan://p@hk.example.com:443#HK").unwrap();
+        let jp = from_str("trojan://p@jp.example.com:443#JP").unwrap();
+        let mut config = config("balance");
+
+        config
+            .use_servers(
+                &[hk.clone(), jp],
+                Strategy::LeastPing,
+                "https://www.gstatic.com/generate_204",
+            )
+            .unwrap();
+        assert_eq!(tags(&config), vec!["proxy-0", "proxy-1", "direct", "block"]);
+
+        let value = json!(config);
+        assert_eq!(
+            value["routing"]["balancers"],
+            json!([{"tag": "proxy", "selector": ["proxy-"], "strategy": {"type": "leastPing"}}])
+        );
+        assert_eq!(
+            value["routing"]["settings"]["rules"],
+            json!([
+                {"type": "field", "domain": ["example.com"], "balancerTag": "proxy"},
+                {"type": "field", "network": "tcp,udp", "balancerTag": "proxy"},
+            ])
+        );
+        assert_eq!(
+            value["observatory"],
+            json!({
+                "subjectSelector": ["proxy-"],
+                "probeURL": "https://www.gstatic.com/generate_204",
+                "probeInterval": "1m",
+            })
+        );
+
+        config.use_server(&hk).unwrap();
+        assert_eq!(tags(&config), vec!["proxy", "direct", "block"]);
+        let value = json!(config);
+        assert!(value["routing"].get("balancers").is_none());
+        assert!(value.get("observatory").is_none());
+        assert_eq!(
+            value["routing"]["settings"]["rules"],
+            json!([{"type": "field", "domain": ["example.com"], "outboundTag": "proxy"}])
+        );
+
+        let _ = fs::remove_file(&config.filepath);
+    }
+
+    #[test]
+    fn random_balancer_has_no_observatory() {
+        let hk = from_str("trojan://p@hk.example.com:443#HK").unwrap();
+        let jp = from_str("trojan://p@jp.example.com:443#JP").unwrap();
+        let mut config = config("random");
+
+        config
+            .use_servers(&[hk, jp], Strategy::Random, "https://example.com")
+            .unwrap();
+        let value = json!(config);
+        assert_eq!(
+            value["routing"]["balancers"][0]["strategy"],
+            json!({"type": "random"})
+        );
+        assert!(value.get("observatory").is_none());
+
+        // nothing to point the proxy traffic at
+        assert!(config
+            .use_servers(&[], Strategy::LeastPing, "https://example.com")
+            .is_err());
+        assert_eq!(tags(&config), vec!["proxy-0", "proxy-1", "direct", "block"]);
+
+        let _ = fs::remove_file(&config.filepath);
+    }
+}
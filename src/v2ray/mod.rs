@@ -1,2 +1,4 @@
 pub mod config;
+pub mod probe;
 pub mod server;
+pub mod subscription;
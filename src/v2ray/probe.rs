@@ -0,0 +1,337 @@
+use crate::errors::kind::ErrorKind;
+use crate::errors::Error;
+use crate::v2ray::server::ServerType;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+/// Measures the delay of a request through a server by starting a
+/// throwaway v2ray instance with a local http inbound and the server as
+/// its only outbound
+pub struct Probe {
+    /// v2ray binary
+    pub bin: String,
+    /// url requested through the server
+    pub url: String,
+    pub timeout: Duration,
+}
+
+impl Probe {
+    /// Returns the time the probe url took to answer through the server
+    pub async fn delay(&self, server: &ServerType) -> Result<Duration, Error> {
+        let reservation = free_port()?;
+        let port = reservation.port;
+        let config =
+            std::env::temp_dir().join(format!("v2up-probe-{}-{}.json", std::process::id(), port));
+        write_config(&config, server, port)?;
+
+        let result = self.run(&config, port).await;
+        let _ = fs::remove_file(&config);
+        result
+    }
+
+    async fn run(&self, config: &PathBuf, port: u16) -> Result<Duration, Error> {
+        let child = Command::new(self.bin.as_str())
+            .arg("-config")
+            .arg(config)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) => return Err(error(format!("start {} err: {}", self.bin, err))),
+        };
+
+        let result = match wait_for_port(&mut child, port, self.timeout).await {
+            Ok(_) => self.request(port).await,
+            Err(err) => Err(err),
+        };
+        let _ = child.kill().await;
+        result
+    }
+
+    async fn request(&self, port: u16) -> Result<Duration, Error> {
+        let proxy = reqwest::Proxy::all(format!("http://127.0.0.1:{}", port).as_str());
+        let client = proxy.and_then(|proxy| {
+            reqwest::Client::builder()
+                .proxy(proxy)
+                .timeout(self.timeout)
+                .build()
+        });
+        let client = match client {
+            Ok(client) => client,
+            Err(err) => return Err(error(format!("create http client err: {}", err))),
+        };
+
+        let start = Instant::now();
+        match client.get(self.url.as_str()).send().await {
+            Ok(response)
+                if response.status().is_success() || response.status().is_redirection() =>
+            {
+                Ok(start.elapsed())
+            }
+            Ok(response) => Err(error(format!(
+                "get {} with status {}",
+                self.url,
+                response.status()
+            ))),
+            Err(err) => Err(error(format!("get {} err: {}", self.url, err))),
+        }
+    }
+}
+
+fn write_config(path: &PathBuf, server: &ServerType, port: u16) -> Result<(), Error> {
+    let config = json!({
+        "log": { "loglevel": "none" },
+        "inbounds": [{
+            "listen": "127.0.0.1",
+            "port": port,
+            "protocol": "http",
+            "settings": {},
+        }],
+        "outbounds": [server.to_outbound()],
+    });
+
+    match fs::write(path, config.to_string()) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Error {
+            kind: ErrorKind::WriteFileError,
+            message: format!("write probe config {} err: {}", path.display(), err),
+        }),
+    }
+}
+
+/// Ports of the running probes. The os may hand a port out again as soon as
+/// its listener is closed, before v2ray binds it, so concurrent probes keep
+/// track of theirs to not share one.
+static RESERVED_PORTS: Mutex<BTreeSet<u16>> = Mutex::new(BTreeSet::new());
+
+/// A port reserved for one probe until dropped
+struct PortReservation {
+    port: u16,
+}
+
+impl Drop for PortReservation {
+    fn drop(&mut self) {
+        if let Ok(mut reserved) = RESERVED_PORTS.lock() {
+            reserved.remove(&self.port);
+        }
+    }
+}
+
+/// Asks the os for a port nobody listens on and no other probe uses
+fn free_port() -> Result<PortReservation, Error> {
+    // ports taken by other probes stay bound until a free one turns up, so
+    // that the os does not hand them out again
+    let mut taken = vec![];
+    for _ in 0..64 {
+        let listener = match TcpListener::bind("127.0.0.1:0") {
+            Ok(listener) => listener,
+            Err(err) => return Err(error(format!("find a free port err: {}", err))),
+        };
+        let port = match listener.local_addr() {
+            Ok(addr) => addr.port(),
+            Err(err) => return Err(error(format!("find a free port err: {}", err))),
+        };
+
+        let mut reserved = match RESERVED_PORTS.lock() {
+            Ok(reserved) => reserved,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if reserved.insert(port) {
+            return Ok(PortReservation { port });
+        }
+        taken.push(listener);
+    }
+    Err(error(String::from("find a free port err: no port left")))
+}
+
+/// Waits until v2ray accepts connections on the inbound port
+async fn wait_for_port(child: &mut Child, port: u16, limit: Duration) -> Result<(), Error> {
+    let start = Instant::now();
+    while start.elapsed() < limit {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(error(format!("v2ray exited with {}", status)));
+        }
+        if TcpStream::connect(("127.0.0.1", port)).await.is_ok() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    Err(error(String::from("v2ray did not listen in time")))
+}
+
+fn error(message: String) -> Error {
+    Error {
+        kind: ErrorKind::LatencyTestError,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::runtime;
+    use crate::v2ray::server::from_str;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::thread;
+
+    /// Runs as the stand-in for v2ray when the test binary is started by
+    /// the fake core script: reads the inbound port from the config and
+    /// forwards plain http proxy requests, answering with the status of
+    /// the upstream response
+    #[test]
+    #[ignore]
+    fn fake_core() {
+        let config = match std::env::var("V2UP_FAKE_CORE_CONFIG") {
+            Ok(config) => config,
+            Err(_) => return,
+        };
+        let config: serde_json::Value =
+            serde_json::from_str(fs::read_to_string(config).unwrap().as_str()).unwrap();
+        let port = config["inbounds"][0]["port"].as_u64().unwrap() as u16;
+
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(&stream);
+            let mut request = String::new();
+            let _ = reader.read_line(&mut request);
+            // the rest of the request is drained, or closing resets the
+            // connection
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+                header.clear();
+            }
+
+            // GET http://127.0.0.1:port/path HTTP/1.1, the probe also
+            // connects without a request to see if the core is up
+            let url = match request.split(' ').nth(1).map(url::Url::parse) {
+                Some(Ok(url)) => url,
+                _ => continue,
+            };
+            let status = std::net::TcpStream::connect((
+                url.host_str().unwrap(),
+                url.port_or_known_default().unwrap(),
+            ))
+            .and_then(|mut upstream| {
+                write!(
+                    upstream,
+                    "GET {} HTTP/1.1\r\nhost: {}\r\nconnection: close\r\n\r\n",
+                    url.path(),
+                    url.host_str().unwrap()
+                )?;
+                let mut response = String::new();
+                BufReader::new(upstream).read_line(&mut response)?;
+                Ok(response
+                    .split_once(' ')
+                    .map_or("", |(_, status)| status)
+                    .trim()
+                    .to_string())
+            })
+            .unwrap_or_else(|_| String::from("502 Bad Gateway"));
+
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                status
+            );
+        }
+    }
+
+    /// Writes an executable script standing in for the v2ray binary
+    #[cfg(unix)]
+    fn write_core(name: &str, content: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// A core starting this test binary as `fake_core` with the config
+    #[cfg(unix)]
+    fn fake_core_bin() -> String {
+        let exe = std::env::current_exe().unwrap();
+        let script = format!(
+            "#!/bin/sh\nV2UP_FAKE_CORE_CONFIG=\"$2\" exec '{}' --exact v2ray::probe::tests::fake_core --ignored --nocapture\n",
+            exe.display()
+        );
+        write_core("v2up-fake-core", script.as_str())
+    }
+
+    /// Answers every request with the status
+    fn serve(status: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/generate_204", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+            }
+        });
+        url
+    }
+
+    fn probe(bin: &str, url: &str) -> Probe {
+        Probe {
+            bin: String::from(bin),
+            url: String::from(url),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn probe_through_core() {
+        let server = from_str("trojan://p@hk.example.com:443#HK").unwrap();
+        let bin = fake_core_bin();
+        let runtime = runtime::new().unwrap();
+
+        let result = runtime.block_on(probe(&bin, &serve("204 No Content")).delay(&server));
+        assert!(result.is_ok());
+
+        let result = runtime.block_on(probe(&bin, &serve("502 Bad Gateway")).delay(&server));
+        assert!(result.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn probe_fails_when_core_exits() {
+        let server = from_str("trojan://p@hk.example.com:443#HK").unwrap();
+        let bin = write_core("v2up-broken-core", "#!/bin/sh\nexit 1\n");
+        let runtime = runtime::new().unwrap();
+
+        let err = runtime
+            .block_on(probe(&bin, &serve("204 No Content")).delay(&server))
+            .unwrap_err();
+        assert!(err.message.contains("exited"));
+    }
+
+    #[test]
+    fn concurrent_probes_get_distinct_ports() {
+        let reservations: Vec<PortReservation> = (0..32).map(|_| free_port().unwrap()).collect();
+        let ports: BTreeSet<u16> = reservations.iter().map(|r| r.port).collect();
+        assert_eq!(ports.len(), reservations.len());
+
+        let port = reservations[0].port;
+        drop(reservations);
+        assert!(!RESERVED_PORTS.lock().unwrap().contains(&port));
+    }
+}
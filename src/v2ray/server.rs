@@ -1,7 +1,11 @@
-use regex::Regex;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use rocket::data::N;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use std::fmt;
 use std::str;
+use url::Url;
 
 use crate::errors::kind::ErrorKind;
 use crate::errors::Error;
@@ -12,12 +16,27 @@ use crate::v2ray::config::SettingsVmess;
 use crate::v2ray::config::User;
 use crate::v2ray::config::Vnext;
 
-use super::config::ServerTrojan;
+use super::config::GrpcSettings;
+use super::config::Header;
+use super::config::HttpSettings;
+use super::config::KcpSettings;
+use super::config::QuicSettings;
+use super::config::RealitySettings;
+use super::config::ServerAddressPassword;
 use super::config::StreamSettings;
 use super::config::TLSSettings;
+use super::config::TcpSettings;
 use super::config::WSSettings;
 use super::config::WSSettingsHeaders;
 
+/// Characters escaped in share link components, everything but the RFC 3986
+/// unreserved set
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum ServerType {
@@ -25,17 +44,154 @@ pub enum ServerType {
     Vmess(VmessServer),
     #[serde(rename = "trojan")]
     Trojan(TrojanServer),
+    #[serde(rename = "vless")]
+    Vless(VlessServer),
+    #[serde(rename = "shadowsocks")]
+    Shadowsocks(ShadowsocksServer),
 }
 
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct VmessServer {
     pub name: String,
+    /// tcp, ws, h2, grpc, kcp or quic
     pub network: String,
     pub user_id: String,
     pub alter_id: i32,
     pub address: String,
     pub port: i32,
+    /// vmess cipher, `scy` in share links
+    #[serde(default)]
+    pub cipher: String,
+    /// header type of tcp, kcp and quic
+    #[serde(default)]
+    pub header_type: String,
+    /// ws/h2 host, or quic security
+    #[serde(default)]
+    pub host: String,
+    /// ws/h2 path, grpc service name, kcp seed or quic key
+    #[serde(default)]
+    pub path: String,
+    /// none or tls
+    #[serde(default)]
+    pub security: String,
+    #[serde(default)]
+    pub sni: String,
+    #[serde(default)]
+    pub alpn: String,
+    #[serde(default)]
+    pub fingerprint: String,
+    #[serde(default)]
+    pub allow_insecure: bool,
+}
+
+impl VmessServer {
+    fn stream_settings(&self) -> StreamSettings {
+        let network = match self.network.as_str() {
+            "ws" | "grpc" | "kcp" | "quic" => self.network.clone(),
+            "h2" | "http" => String::from("h2"),
+            _ => String::from("tcp"),
+        };
+        let header_type = match self.header_type.is_empty() {
+            true => String::from("none"),
+            false => self.header_type.clone(),
+        };
+        let hosts: Vec<String> = self
+            .host
+            .split(',')
+            .map(|host| host.trim().to_string())
+            .filter(|host| !host.is_empty())
+            .collect();
+        let path = match self.path.is_empty() {
+            true => String::from("/"),
+            false => self.path.clone(),
+        };
+
+        let mut stream_settings = StreamSettings {
+            network: network.clone(),
+            security: String::from("none"),
+            ..Default::default()
+        };
+
+        if self.security == "tls" {
+            stream_settings.security = String::from("tls");
+            stream_settings.tls_settings = Some(TLSSettings {
+                server_name: match (self.sni.is_empty(), hosts.first()) {
+                    (false, _) => self.sni.clone(),
+                    (true, Some(host)) => host.clone(),
+                    (true, None) => self.address.clone(),
+                },
+                allow_insecure: self.allow_insecure,
+                allow_insecure_ciphers: self.allow_insecure,
+                alpn: match self.alpn.is_empty() {
+                    true => None,
+                    false => Some(self.alpn.split(',').map(|p| p.trim().to_string()).collect()),
+                },
+                fingerprint: match self.fingerprint.is_empty() {
+                    true => None,
+                    false => Some(self.fingerprint.clone()),
+                },
+            });
+        }
+
+        match network.as_str() {
+            "ws" => {
+                stream_settings.ws_settings = Some(WSSettings {
+                    path,
+                    headers: WSSettingsHeaders {
+                        host: hosts.first().cloned().unwrap_or_default(),
+                    },
+                })
+            }
+            "h2" => stream_settings.http_settings = Some(HttpSettings { host: hosts, path }),
+            "grpc" => {
+                stream_settings.grpc_settings = Some(GrpcSettings {
+                    service_name: self.path.clone(),
+                })
+            }
+            "kcp" => {
+                stream_settings.kcp_settings = Some(KcpSettings {
+                    header: Header {
+                        type_field: header_type,
+                        request: None,
+                    },
+                    seed: match self.path.is_empty() {
+                        true => None,
+                        false => Some(self.path.clone()),
+                    },
+                })
+            }
+            "quic" => {
+                stream_settings.quic_settings = Some(QuicSettings {
+                    security: match self.host.is_empty() {
+                        true => String::from("none"),
+                        false => self.host.clone(),
+                    },
+                    key: self.path.clone(),
+                    header: Header {
+                        type_field: header_type,
+                        request: None,
+                    },
+                })
+            }
+            _ => {
+                stream_settings.tcp_settings = Some(TcpSettings {
+                    header: Header {
+                        request: match header_type.as_str() {
+                            "http" => Some(json!({
+                                "path": [path],
+                                "headers": { "Host": hosts },
+                            })),
+                            _ => None,
+                        },
+                        type_field: header_type,
+                    },
+                })
+            }
+        }
+
+        stream_settings
+    }
 }
 
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -50,6 +206,8 @@ pub struct TrojanServer {
     pub network: String,
     #[serde(default)]
     pub path: String,
+    #[serde(default)]
+    pub host: String,
 }
 
 impl TrojanServer {
@@ -63,28 +221,289 @@ impl TrojanServer {
             allow_insecure: false,
             network: String::from(""),
             path: String::from(""),
+            host: String::from(""),
         };
     }
 }
 
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VlessServer {
+    pub name: String,
+    pub address: String,
+    pub port: i32,
+    pub user_id: String,
+    pub encryption: String,
+    pub flow: String,
+    /// none, tls or reality
+    pub security: String,
+    pub sni: String,
+    pub allow_insecure: bool,
+    pub fingerprint: String,
+    pub public_key: String,
+    pub short_id: String,
+    pub spider_x: String,
+    /// tcp, ws or grpc
+    pub network: String,
+    pub path: String,
+    pub host: String,
+    pub service_name: String,
+}
+
+#[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ShadowsocksServer {
+    pub name: String,
+    pub address: String,
+    pub port: i32,
+    pub method: String,
+    pub password: String,
+    /// SIP003 plugin name, e.g. obfs-local or v2ray-plugin
+    pub plugin: String,
+    /// SIP003 plugin options, e.g. obfs=http;obfs-host=example.com
+    pub plugin_opts: String,
+}
+
+impl ShadowsocksServer {
+    /// Returns the value of a `key=value` entry in plugin_opts, or
+    /// "" for a bare flag like `tls`
+    fn plugin_opt(&self, key: &str) -> Option<&str> {
+        self.plugin_opts
+            .split(';')
+            .find_map(|opt| match opt.split_once('=') {
+                Some((k, v)) => (k == key).then_some(v),
+                None => (opt == key).then_some(""),
+            })
+    }
+
+    /// Fails for plugins v2ray has no transport for, as such servers would
+    /// be written as plain shadowsocks outbounds that never connect
+    pub fn check_plugin(&self) -> Result<(), Error> {
+        match self.plugin.as_str() {
+            "" => Ok(()),
+            "v2ray-plugin"
+                if self
+                    .plugin_opt("mode")
+                    .is_none_or(|mode| mode == "websocket") =>
+            {
+                Ok(())
+            }
+            plugin => Err(Error {
+                kind: ErrorKind::ParseServerURLError,
+                message: format!(
+                    "shadowsocks server {} uses unsupported plugin {} {}",
+                    self.name, plugin, self.plugin_opts
+                ),
+            }),
+        }
+    }
+}
+
+#[serde_as]
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", default)]
 struct VmessServerInfo {
-    pub v: String,
+    pub v: serde_json::Value,
     pub ps: String,
     pub add: String,
-    pub port: String,
+    #[serde_as(as = "PickFirst<(DisplayFromStr, _)>")]
+    pub port: i32,
     pub id: String,
-    pub aid: String,
+    #[serde_as(as = "PickFirst<(DisplayFromStr, _)>")]
+    pub aid: i32,
+    pub scy: String,
     pub net: String,
     #[serde(rename = "type")]
     pub ty: String,
     pub host: String,
     pub path: String,
     pub tls: String,
+    pub sni: String,
+    pub alpn: String,
+    pub fp: String,
+}
+
+/// Protocol, endpoint and credential of a server
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct ServerIdentity {
+    pub protocol: String,
+    pub address: String,
+    pub port: i32,
+    pub credential: String,
+}
+
+impl fmt::Display for ServerIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}://{}:{}", self.protocol, self.address, self.port)
+    }
 }
 
 impl ServerType {
+    pub fn name(&self) -> &str {
+        match self {
+            ServerType::Vmess(server) => server.name.as_str(),
+            ServerType::Trojan(server) => server.name.as_str(),
+            ServerType::Vless(server) => server.name.as_str(),
+            ServerType::Shadowsocks(server) => server.name.as_str(),
+        }
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        match self {
+            ServerType::Vmess(server) => server.name = name,
+            ServerType::Trojan(server) => server.name = name,
+            ServerType::Vless(server) => server.name = name,
+            ServerType::Shadowsocks(server) => server.name = name,
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        match self {
+            ServerType::Vmess(server) => server.address.as_str(),
+            ServerType::Trojan(server) => server.address.as_str(),
+            ServerType::Vless(server) => server.address.as_str(),
+            ServerType::Shadowsocks(server) => server.address.as_str(),
+        }
+    }
+
+    pub fn port(&self) -> i32 {
+        match self {
+            ServerType::Vmess(server) => server.port,
+            ServerType::Trojan(server) => server.port,
+            ServerType::Vless(server) => server.port,
+            ServerType::Shadowsocks(server) => server.port,
+        }
+    }
+
+    /// Server name of the TLS handshake, None for servers without TLS
+    pub fn tls_server_name(&self) -> Option<&str> {
+        let (security, sni) = match self {
+            ServerType::Vmess(server) => (server.security.as_str(), server.sni.as_str()),
+            ServerType::Trojan(server) => ("tls", server.sni.as_str()),
+            ServerType::Vless(server) => (server.security.as_str(), server.sni.as_str()),
+            ServerType::Shadowsocks(_) => return None,
+        };
+
+        match security {
+            "tls" | "reality" if !sni.is_empty() => Some(sni),
+            "tls" | "reality" => Some(self.address()),
+            _ => None,
+        }
+    }
+
+    /// What tells two servers apart regardless of their names and
+    /// transport settings
+    pub fn identity(&self) -> ServerIdentity {
+        let (protocol, credential) = match self {
+            ServerType::Vmess(server) => ("vmess", server.user_id.clone()),
+            ServerType::Trojan(server) => ("trojan", server.password.clone()),
+            ServerType::Vless(server) => ("vless", server.user_id.clone()),
+            ServerType::Shadowsocks(server) => (
+                "shadowsocks",
+                format!("{}:{}", server.method, server.password),
+            ),
+        };
+
+        ServerIdentity {
+            protocol: String::from(protocol),
+            address: String::from(self.address()),
+            port: self.port(),
+            credential,
+        }
+    }
+
+    /// Builds the share link of the server, the inverse of `from_str`
+    pub fn to_url(&self) -> String {
+        match self {
+            ServerType::Vmess(server) => {
+                let info = VmessServerInfo {
+                    v: serde_json::Value::from("2"),
+                    ps: server.name.clone(),
+                    add: server.address.clone(),
+                    port: server.port,
+                    id: server.user_id.clone(),
+                    aid: server.alter_id,
+                    scy: server.cipher.clone(),
+                    net: server.network.clone(),
+                    ty: server.header_type.clone(),
+                    host: server.host.clone(),
+                    path: server.path.clone(),
+                    tls: server.security.clone(),
+                    sni: server.sni.clone(),
+                    alpn: server.alpn.clone(),
+                    fp: server.fingerprint.clone(),
+                };
+                let json_data = serde_json::to_string(&info).unwrap();
+                format!("vmess://{}", base64::encode(json_data))
+            }
+            ServerType::Trojan(server) => {
+                let query = encode_query(&[
+                    ("sni", server.sni.as_str()),
+                    (
+                        "allowInsecure",
+                        if server.allow_insecure { "1" } else { "" },
+                    ),
+                    ("type", server.network.as_str()),
+                    ("path", server.path.as_str()),
+                    ("host", server.host.as_str()),
+                ]);
+                format!(
+                    "trojan://{}@{}{}#{}",
+                    percent_encode(server.password.as_str()),
+                    host_port(server.address.as_str(), server.port),
+                    query,
+                    percent_encode(server.name.as_str())
+                )
+            }
+            ServerType::Vless(server) => {
+                let query = encode_query(&[
+                    ("encryption", server.encryption.as_str()),
+                    ("flow", server.flow.as_str()),
+                    ("security", server.security.as_str()),
+                    ("sni", server.sni.as_str()),
+                    (
+                        "allowInsecure",
+                        if server.allow_insecure { "1" } else { "" },
+                    ),
+                    ("fp", server.fingerprint.as_str()),
+                    ("pbk", server.public_key.as_str()),
+                    ("sid", server.short_id.as_str()),
+                    ("spx", server.spider_x.as_str()),
+                    ("type", server.network.as_str()),
+                    ("path", server.path.as_str()),
+                    ("host", server.host.as_str()),
+                    ("serviceName", server.service_name.as_str()),
+                ]);
+                format!(
+                    "vless://{}@{}{}#{}",
+                    percent_encode(server.user_id.as_str()),
+                    host_port(server.address.as_str(), server.port),
+                    query,
+                    percent_encode(server.name.as_str())
+                )
+            }
+            ServerType::Shadowsocks(server) => {
+                let user_info = base64::encode_config(
+                    format!("{}:{}", server.method, server.password),
+                    base64::URL_SAFE_NO_PAD,
+                );
+                let plugin = match server.plugin_opts.is_empty() {
+                    true => server.plugin.clone(),
+                    false => format!("{};{}", server.plugin, server.plugin_opts),
+                };
+                let query = encode_query(&[("plugin", plugin.as_str())]);
+                format!(
+                    "ss://{}@{}{}{}#{}",
+                    user_info,
+                    host_port(server.address.as_str(), server.port),
+                    if query.is_empty() { "" } else { "/" },
+                    query,
+                    percent_encode(server.name.as_str())
+                )
+            }
+        }
+    }
+
     pub fn to_outbound(&self) -> Outbound {
         match self {
             ServerType::Trojan(server) => {
@@ -92,10 +511,11 @@ impl ServerType {
                     mux: None,
                     protocol: String::from("trojan"),
                     settings: OutboundSettings {
-                        servers: Some(vec![ServerTrojan {
+                        servers: Some(vec![ServerAddressPassword {
                             address: server.address.clone(),
                             port: server.port,
                             password: server.password.clone(),
+                            method: None,
                         }]),
                         vnext: None,
                     },
@@ -109,16 +529,22 @@ impl ServerType {
                             server_name: server.sni.clone(),
                             allow_insecure: server.allow_insecure,
                             allow_insecure_ciphers: server.allow_insecure,
+                            alpn: None,
+                            fingerprint: None,
                         }),
                         ws_settings: match server.network.as_str() {
                             "ws" => Some(WSSettings {
                                 path: server.path.clone(),
                                 headers: WSSettingsHeaders {
-                                    host: server.sni.clone(),
+                                    host: match server.host.is_empty() {
+                                        true => server.sni.clone(),
+                                        false => server.host.clone(),
+                                    },
                                 },
                             }),
                             _ => None,
                         },
+                        ..Default::default()
                     }),
                     tag: String::from("proxy"),
                 };
@@ -134,18 +560,149 @@ impl ServerType {
                             port: server.port,
                             users: vec![User {
                                 id: server.user_id.clone(),
-                                alter_id: server.alter_id,
+                                alter_id: Some(server.alter_id),
                                 // level: 0,
-                                security: String::from("aes-128-gcm"),
+                                security: Some(match server.cipher.is_empty() {
+                                    true => String::from("auto"),
+                                    false => server.cipher.clone(),
+                                }),
+                                encryption: None,
+                                flow: None,
                             }],
                         }]),
                         servers: None,
                     },
+                    stream_settings: Some(server.stream_settings()),
+                };
+            }
+            ServerType::Vless(server) => {
+                return Outbound {
+                    mux: None,
+                    protocol: String::from("vless"),
+                    tag: String::from("proxy"),
+                    settings: OutboundSettings {
+                        vnext: Some(vec![Vnext {
+                            address: server.address.clone(),
+                            port: server.port,
+                            users: vec![User {
+                                id: server.user_id.clone(),
+                                alter_id: None,
+                                security: None,
+                                encryption: Some(match server.encryption.is_empty() {
+                                    true => String::from("none"),
+                                    false => server.encryption.clone(),
+                                }),
+                                flow: match server.flow.is_empty() {
+                                    true => None,
+                                    false => Some(server.flow.clone()),
+                                },
+                            }],
+                        }]),
+                        servers: None,
+                    },
+                    stream_settings: Some(StreamSettings {
+                        network: match server.network.as_str() {
+                            "ws" | "grpc" => server.network.clone(),
+                            _ => String::from("tcp"),
+                        },
+                        security: match server.security.as_str() {
+                            "tls" | "reality" => server.security.clone(),
+                            _ => String::from("none"),
+                        },
+                        tls_settings: match server.security.as_str() {
+                            "tls" => Some(TLSSettings {
+                                server_name: server.sni.clone(),
+                                allow_insecure: server.allow_insecure,
+                                allow_insecure_ciphers: server.allow_insecure,
+                                alpn: None,
+                                fingerprint: match server.fingerprint.is_empty() {
+                                    true => None,
+                                    false => Some(server.fingerprint.clone()),
+                                },
+                            }),
+                            _ => None,
+                        },
+                        ws_settings: match server.network.as_str() {
+                            "ws" => Some(WSSettings {
+                                path: server.path.clone(),
+                                headers: WSSettingsHeaders {
+                                    host: match server.host.is_empty() {
+                                        true => server.sni.clone(),
+                                        false => server.host.clone(),
+                                    },
+                                },
+                            }),
+                            _ => None,
+                        },
+                        grpc_settings: match server.network.as_str() {
+                            "grpc" => Some(GrpcSettings {
+                                service_name: server.service_name.clone(),
+                            }),
+                            _ => None,
+                        },
+                        reality_settings: match server.security.as_str() {
+                            "reality" => Some(RealitySettings {
+                                server_name: server.sni.clone(),
+                                fingerprint: server.fingerprint.clone(),
+                                public_key: server.public_key.clone(),
+                                short_id: server.short_id.clone(),
+                                spider_x: server.spider_x.clone(),
+                            }),
+                            _ => None,
+                        },
+                        ..Default::default()
+                    }),
+                };
+            }
+            ServerType::Shadowsocks(server) => {
+                // v2ray core has no SIP003 plugin support, only v2ray-plugin
+                // maps onto a native transport (websocket with optional tls)
+                let v2ray_plugin = server.plugin == "v2ray-plugin";
+                let tls = v2ray_plugin && server.plugin_opt("tls").is_some();
+                let host = server.plugin_opt("host").unwrap_or(server.address.as_str());
+
+                return Outbound {
+                    mux: None,
+                    protocol: String::from("shadowsocks"),
+                    tag: String::from("proxy"),
+                    settings: OutboundSettings {
+                        servers: Some(vec![ServerAddressPassword {
+                            address: server.address.clone(),
+                            port: server.port,
+                            password: server.password.clone(),
+                            method: Some(server.method.clone()),
+                        }]),
+                        vnext: None,
+                    },
                     stream_settings: Some(StreamSettings {
-                        network: String::from("tcp"),
-                        security: String::from("none"),
-                        tls_settings: None,
-                        ws_settings: None,
+                        network: match v2ray_plugin {
+                            true => String::from("ws"),
+                            false => String::from("tcp"),
+                        },
+                        security: match tls {
+                            true => String::from("tls"),
+                            false => String::from("none"),
+                        },
+                        tls_settings: match tls {
+                            true => Some(TLSSettings {
+                                server_name: String::from(host),
+                                allow_insecure: false,
+                                allow_insecure_ciphers: false,
+                                alpn: None,
+                                fingerprint: None,
+                            }),
+                            false => None,
+                        },
+                        ws_settings: match v2ray_plugin {
+                            true => Some(WSSettings {
+                                path: String::from(server.plugin_opt("path").unwrap_or("/")),
+                                headers: WSSettingsHeaders {
+                                    host: String::from(host),
+                                },
+                            }),
+                            false => None,
+                        },
+                        ..Default::default()
                     }),
                 };
             }
@@ -154,59 +711,274 @@ impl ServerType {
 }
 
 pub fn from_str(server_url: &str) -> Result<ServerType, Error> {
-    let parts: Vec<&str> = server_url.split("://").collect();
+    let (protocol, data) = server_url.split_once("://").unwrap_or((server_url, ""));
 
-    return match parts[0] {
-        "vmess" => parse_vmess_server(parts[1]),
-        "trojan" => parse_trojan_server(parts[1]),
+    return match protocol {
+        "vmess" => parse_vmess_server(data),
+        "trojan" => parse_trojan_server(data),
+        "vless" => parse_vless_server(data),
+        "ss" => parse_shadowsocks_server(data),
         _ => Err(Error {
             kind: ErrorKind::UnknownServerProtocol,
-            message: format!("unknown server protocol: {}, {}", parts[0], server_url),
+            message: format!("unknown server protocol: {}, {}", protocol, server_url),
         }),
     };
 }
 
 fn parse_trojan_server(data: &str) -> Result<ServerType, Error> {
-    let server = TrojanServer::new();
+    let mut server = TrojanServer::new();
+
+    // data: 31b98cae-da2d-4456-b351-f91838313f0a@jp1.lxjc.app:443?allowInsecure=0&peer=16-163-218-240.nhost.00cdn.com&sni=16-163-218-240.nhost.00cdn.com#%E5%89%A9%E4%BD%99%E6%B5%81%E9%87%8F%EF%BC%9A99.89%20GB
+    let url = match Url::parse(format!("trojan://{}", data.trim()).as_str()) {
+        Ok(url) => url,
+        Err(err) => {
+            return Err(Error {
+                kind: ErrorKind::ParseServerURLError,
+                message: format!("err trojan server url: {}, {}", err, data),
+            })
+        }
+    };
 
-    // data: trojan://31b98cae-da2d-4456-b351-f91838313f0a@jp1.lxjc.app:443?allowInsecure=0&peer=16-163-218-240.nhost.00cdn.com&sni=16-163-218-240.nhost.00cdn.com#%E5%89%A9%E4%BD%99%E6%B5%81%E9%87%8F%EF%BC%9A99.89%20GB
-    let re = Regex::new("trojan://(.*)@(.*):(.*)?(.*)#(.*)").unwrap();
+    server.password = percent_decode(url.username());
+    server.address = match url.host_str() {
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        None => String::from(""),
+    };
+    server.port = i32::from(url.port().unwrap_or(443));
+    server.name = percent_decode(url.fragment().unwrap_or(""));
+    server.network = String::from("tcp");
 
-    if !re.is_match(data) {
+    if server.password.is_empty() || server.address.is_empty() {
         return Err(Error {
-            kind: ErrorKind::UnknownServerProtocol,
-            message: format!("err trojan server url: {}", data),
+            kind: ErrorKind::ParseServerURLError,
+            message: format!("err trojan server url, missing password or host: {}", data),
         });
     }
 
-    for cap in re.captures_iter(data) {
-        println!("{}", &cap[0]);
+    let mut peer = String::from("");
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "sni" => server.sni = value.to_string(),
+            "peer" => peer = value.to_string(),
+            "allowInsecure" => server.allow_insecure = value == "1" || value == "true",
+            "type" => server.network = value.to_string(),
+            "path" => server.path = value.to_string(),
+            "host" => server.host = value.to_string(),
+            _ => {}
+        }
     }
 
-    // handle part2
+    // `peer` is the legacy name of `sni` used by some clients
+    if server.sni.is_empty() {
+        server.sni = peer;
+    }
+
+    if server.name.is_empty() {
+        server.name = format!("{}:{}", server.address, server.port);
+    }
 
     Ok(ServerType::Trojan(server))
 }
 
-fn parse_vmess_server(data: &str) -> Result<ServerType, Error> {
-    let result = base64::decode(data);
-    if result.is_err() {
+fn parse_vless_server(data: &str) -> Result<ServerType, Error> {
+    let mut server = VlessServer::default();
+
+    // data: 5f1a6a8e-8d4c-4b6e-9d1f-2a3b4c5d6e7f@sg1.example.com:443?encryption=none&flow=xtls-rprx-vision&security=reality&sni=www.microsoft.com&fp=chrome&pbk=...&sid=6ba85179e30d4fc2&type=tcp#SG%2001
+    let url = match Url::parse(format!("vless://{}", data.trim()).as_str()) {
+        Ok(url) => url,
+        Err(err) => {
+            return Err(Error {
+                kind: ErrorKind::ParseServerURLError,
+                message: format!("err vless server url: {}, {}", err, data),
+            })
+        }
+    };
+
+    server.user_id = percent_decode(url.username());
+    server.address = match url.host_str() {
+        Some(host) => host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string(),
+        None => String::from(""),
+    };
+    server.port = i32::from(url.port().unwrap_or(443));
+    server.name = percent_decode(url.fragment().unwrap_or(""));
+    server.encryption = String::from("none");
+    server.security = String::from("none");
+    server.network = String::from("tcp");
+
+    if server.user_id.is_empty() || server.address.is_empty() {
         return Err(Error {
-            kind: ErrorKind::Base64DecodeError,
-            message: format!(
-                "decode server info err: {}, source: {}",
-                result.err().unwrap(),
-                data
-            ),
+            kind: ErrorKind::ParseServerURLError,
+            message: format!("err vless server url, missing id or host: {}", data),
         });
     }
 
-    let bytes = result.unwrap();
-    let json_data = match str::from_utf8(bytes.as_slice()) {
-        Ok(v) => v,
-        Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "encryption" => server.encryption = value.to_string(),
+            "flow" => server.flow = value.to_string(),
+            "security" => server.security = value.to_string(),
+            "sni" | "peer" => server.sni = value.to_string(),
+            "allowInsecure" => server.allow_insecure = value == "1" || value == "true",
+            "fp" => server.fingerprint = value.to_string(),
+            "pbk" => server.public_key = value.to_string(),
+            "sid" => server.short_id = value.to_string(),
+            "spx" => server.spider_x = value.to_string(),
+            "type" => server.network = value.to_string(),
+            "path" => server.path = value.to_string(),
+            "host" => server.host = value.to_string(),
+            "serviceName" => server.service_name = value.to_string(),
+            _ => {}
+        }
+    }
+
+    if server.name.is_empty() {
+        server.name = format!("{}:{}", server.address, server.port);
+    }
+
+    Ok(ServerType::Vless(server))
+}
+
+fn parse_shadowsocks_server(data: &str) -> Result<ServerType, Error> {
+    let mut server = ShadowsocksServer::default();
+
+    // SIP002: YWVzLTI1Ni1nY206cGFzc3dvcmQ@198.51.100.1:8888/?plugin=obfs-local%3Bobfs%3Dhttp#Example
+    // legacy: YWVzLTI1Ni1nY206cGFzc3dvcmRAMTk4LjUxLjEwMC4xOjg4ODg#Example
+    let (data, name) = data.trim().split_once('#').unwrap_or((data.trim(), ""));
+    let (data, query) = data.split_once('?').unwrap_or((data, ""));
+    let data = data.trim_end_matches('/');
+
+    let (user_info, host_port) = match data.rsplit_once('@') {
+        Some((user_info, host_port)) => {
+            let user_info = match decode_base64(user_info) {
+                Ok(decoded) if decoded.contains(':') => decoded,
+                // SIP002 allows percent encoded plain user info for AEAD-2022 ciphers
+                _ => percent_decode(user_info),
+            };
+            (user_info, String::from(host_port))
+        }
+        None => {
+            let decoded = decode_base64(data)?;
+            match decoded.rsplit_once('@') {
+                Some((user_info, host_port)) => (String::from(user_info), String::from(host_port)),
+                None => {
+                    return Err(Error {
+                        kind: ErrorKind::ParseServerURLError,
+                        message: format!("err shadowsocks server url, missing host: {}", data),
+                    })
+                }
+            }
+        }
     };
-    let result = serde_json::from_str(json_data);
+
+    match user_info.split_once(':') {
+        Some((method, password)) => {
+            server.method = String::from(method);
+            server.password = String::from(password);
+        }
+        None => {
+            return Err(Error {
+                kind: ErrorKind::ParseServerURLError,
+                message: format!("err shadowsocks server url, missing method: {}", data),
+            })
+        }
+    }
+
+    let port = match host_port.rsplit_once(':') {
+        Some((host, port)) => {
+            server.address = host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string();
+            port.parse::<u16>().ok()
+        }
+        None => None,
+    };
+    server.port = match port {
+        Some(port) => i32::from(port),
+        None => {
+            return Err(Error {
+                kind: ErrorKind::ParseServerURLError,
+                message: format!("err shadowsocks server url, invalid port: {}", host_port),
+            })
+        }
+    };
+
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        if key == "plugin" {
+            let (plugin, opts) = value.split_once(';').unwrap_or((value.as_ref(), ""));
+            server.plugin = String::from(plugin);
+            server.plugin_opts = String::from(opts);
+        }
+    }
+
+    server.name = percent_decode(name);
+    if server.name.is_empty() {
+        server.name = format!("{}:{}", server.address, server.port);
+    }
+    server.check_plugin()?;
+
+    Ok(ServerType::Shadowsocks(server))
+}
+
+/// Decodes standard or url safe base64, padded or not, into a utf-8 string
+pub fn decode_base64(data: &str) -> Result<String, Error> {
+    let data = data.trim().trim_end_matches('=');
+    let result = base64::decode_config(data, base64::STANDARD_NO_PAD)
+        .or_else(|_| base64::decode_config(data, base64::URL_SAFE_NO_PAD));
+
+    match result {
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(decoded) => Ok(decoded),
+            Err(err) => Err(Error {
+                kind: ErrorKind::Base64DecodeError,
+                message: format!("decoded base64 is not utf-8: {}, source: {}", err, data),
+            }),
+        },
+        Err(err) => Err(Error {
+            kind: ErrorKind::Base64DecodeError,
+            message: format!("decode base64 err: {}, source: {}", err, data),
+        }),
+    }
+}
+
+fn percent_encode(data: &str) -> String {
+    utf8_percent_encode(data, COMPONENT).to_string()
+}
+
+/// Encodes non-empty params into a query string with the leading `?`
+fn encode_query(params: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = params
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(key, value)| format!("{}={}", key, percent_encode(value)))
+        .collect();
+
+    match pairs.is_empty() {
+        true => String::from(""),
+        false => format!("?{}", pairs.join("&")),
+    }
+}
+
+fn host_port(address: &str, port: i32) -> String {
+    match address.contains(':') {
+        true => format!("[{}]:{}", address, port),
+        false => format!("{}:{}", address, port),
+    }
+}
+
+fn percent_decode(data: &str) -> String {
+    percent_decode_str(data).decode_utf8_lossy().to_string()
+}
+
+fn parse_vmess_server(data: &str) -> Result<ServerType, Error> {
+    let json_data = decode_base64(data)?;
+    let result = serde_json::from_str(json_data.as_str());
     if result.is_err() {
         return Err(Error {
             kind: ErrorKind::Base64DecodeError,
@@ -223,10 +995,392 @@ fn parse_vmess_server(data: &str) -> Result<ServerType, Error> {
         name: server_info.ps,
         network: server_info.net,
         address: server_info.add,
-        port: server_info.port.parse().unwrap(),
+        port: server_info.port,
         user_id: server_info.id,
-        alter_id: server_info.aid.parse().unwrap(),
+        alter_id: server_info.aid,
+        cipher: server_info.scy,
+        header_type: server_info.ty,
+        host: server_info.host,
+        path: server_info.path,
+        security: server_info.tls,
+        sni: server_info.sni,
+        alpn: server_info.alpn,
+        fingerprint: server_info.fp,
+        allow_insecure: false,
     };
 
     Ok(ServerType::Vmess(vmess_server))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vmess_link(info: serde_json::Value) -> String {
+        format!("vmess://{}", base64::encode(info.to_string()))
+    }
+
+    #[test]
+    fn parse_vmess_ws_tls() {
+        let server = from_str(
+            vmess_link(json!({
+                "v": "2", "ps": "HK 01", "add": "hk.example.com", "port": "443",
+                "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": "0", "scy": "chacha20-poly1305",
+                "net": "ws", "type": "none", "host": "cdn.example.com", "path": "/vmess",
+                "tls": "tls", "sni": "sni.example.com", "alpn": "h2,http/1.1", "fp": "chrome"
+            }))
+            .as_str(),
+        )
+        .unwrap();
+
+        let outbound = server.to_outbound();
+        let user = &outbound.settings.vnext.unwrap()[0].users[0];
+        assert_eq!(user.security, Some(String::from("chacha20-poly1305")));
+        assert_eq!(user.alter_id, Some(0));
+
+        let stream_settings = outbound.stream_settings.unwrap();
+        assert_eq!(stream_settings.network, "ws");
+        assert_eq!(stream_settings.security, "tls");
+        let tls_settings = stream_settings.tls_settings.unwrap();
+        assert_eq!(tls_settings.server_name, "sni.example.com");
+        assert_eq!(
+            tls_settings.alpn,
+            Some(vec![String::from("h2"), String::from("http/1.1")])
+        );
+        assert_eq!(tls_settings.fingerprint, Some(String::from("chrome")));
+        let ws_settings = stream_settings.ws_settings.unwrap();
+        assert_eq!(ws_settings.path, "/vmess");
+        assert_eq!(ws_settings.headers.host, "cdn.example.com");
+    }
+
+    #[test]
+    fn parse_vmess_numeric_fields_and_defaults() {
+        let server = from_str(
+            vmess_link(json!({
+                "v": 2, "ps": "JP", "add": "jp.example.com", "port": 10086,
+                "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": 2, "net": "tcp"
+            }))
+            .as_str(),
+        )
+        .unwrap();
+
+        let outbound = server.to_outbound();
+        match &server {
+            ServerType::Vmess(server) => {
+                assert_eq!(server.port, 10086);
+                assert_eq!(server.alter_id, 2);
+            }
+            _ => panic!("expect vmess server"),
+        }
+        let user = &outbound.settings.vnext.unwrap()[0].users[0];
+        assert_eq!(user.security, Some(String::from("auto")));
+        let stream_settings = outbound.stream_settings.unwrap();
+        assert_eq!(stream_settings.network, "tcp");
+        assert_eq!(stream_settings.security, "none");
+        assert!(stream_settings.tls_settings.is_none());
+        assert_eq!(
+            stream_settings.tcp_settings.unwrap().header.type_field,
+            "none"
+        );
+    }
+
+    #[test]
+    fn parse_vmess_other_transports() {
+        let info = json!({
+            "v": "2", "ps": "t", "add": "t.example.com", "port": "443",
+            "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": "0",
+            "net": "grpc", "type": "none", "host": "", "path": "svc", "tls": "tls"
+        });
+        let stream_settings = from_str(vmess_link(info).as_str())
+            .unwrap()
+            .to_outbound()
+            .stream_settings
+            .unwrap();
+        assert_eq!(stream_settings.grpc_settings.unwrap().service_name, "svc");
+        assert_eq!(
+            stream_settings.tls_settings.unwrap().server_name,
+            "t.example.com"
+        );
+
+        let info = json!({
+            "v": "2", "ps": "t", "add": "t.example.com", "port": "443",
+            "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": "0",
+            "net": "h2", "type": "none", "host": "a.example.com,b.example.com", "path": "/h2", "tls": "tls"
+        });
+        let stream_settings = from_str(vmess_link(info).as_str())
+            .unwrap()
+            .to_outbound()
+            .stream_settings
+            .unwrap();
+        assert_eq!(stream_settings.network, "h2");
+        let http_settings = stream_settings.http_settings.unwrap();
+        assert_eq!(http_settings.host, vec!["a.example.com", "b.example.com"]);
+        assert_eq!(http_settings.path, "/h2");
+
+        let info = json!({
+            "v": "2", "ps": "t", "add": "t.example.com", "port": "443",
+            "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": "0",
+            "net": "kcp", "type": "wechat-video", "host": "", "path": "seed", "tls": ""
+        });
+        let kcp_settings = from_str(vmess_link(info).as_str())
+            .unwrap()
+            .to_outbound()
+            .stream_settings
+            .unwrap()
+            .kcp_settings
+            .unwrap();
+        assert_eq!(kcp_settings.header.type_field, "wechat-video");
+        assert_eq!(kcp_settings.seed, Some(String::from("seed")));
+    }
+
+    #[test]
+    fn parse_trojan_with_peer_and_sni() {
+        let server = from_str("trojan://31b98cae-da2d-4456-b351-f91838313f0a@jp1.lxjc.app:443?allowInsecure=0&peer=16-163-218-240.nhost.00cdn.com&sni=16-163-218-240.nhost.00cdn.com#%E5%89%A9%E4%BD%99%E6%B5%81%E9%87%8F%EF%BC%9A99.89%20GB").unwrap();
+
+        assert_eq!(
+            server,
+            ServerType::Trojan(TrojanServer {
+                name: String::from("剩余流量：99.89 GB"),
+                address: String::from("jp1.lxjc.app"),
+                port: 443,
+                password: String::from("31b98cae-da2d-4456-b351-f91838313f0a"),
+                sni: String::from("16-163-218-240.nhost.00cdn.com"),
+                allow_insecure: false,
+                network: String::from("tcp"),
+                path: String::from(""),
+                host: String::from(""),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_trojan_over_websocket() {
+        let server = from_str("trojan://p%40ss@hk2.example.com:8443?allowInsecure=1&peer=cdn.example.com&type=ws&path=%2Fws%3Fed%3D2048&host=ws.example.com#HK%2002").unwrap();
+
+        match server {
+            ServerType::Trojan(server) => {
+                assert_eq!(server.name, "HK 02");
+                assert_eq!(server.password, "p@ss");
+                assert_eq!(server.port, 8443);
+                assert_eq!(server.sni, "cdn.example.com");
+                assert!(server.allow_insecure);
+                assert_eq!(server.network, "ws");
+                assert_eq!(server.path, "/ws?ed=2048");
+                assert_eq!(server.host, "ws.example.com");
+            }
+            _ => panic!("expect trojan server"),
+        }
+    }
+
+    #[test]
+    fn parse_trojan_without_port_and_name() {
+        let server = from_str("trojan://secret@[2001:db8::1]").unwrap();
+
+        match server {
+            ServerType::Trojan(server) => {
+                assert_eq!(server.address, "2001:db8::1");
+                assert_eq!(server.port, 443);
+                assert_eq!(server.name, "2001:db8::1:443");
+            }
+            _ => panic!("expect trojan server"),
+        }
+    }
+
+    #[test]
+    fn parse_trojan_without_password() {
+        assert!(from_str("trojan://jp1.lxjc.app:443#name").is_err());
+    }
+
+    #[test]
+    fn parse_vless_with_reality() {
+        let server = from_str("vless://5f1a6a8e-8d4c-4b6e-9d1f-2a3b4c5d6e7f@sg1.example.com:443?encryption=none&flow=xtls-rprx-vision&security=reality&sni=www.microsoft.com&fp=chrome&pbk=Z84J2IelR9ch3k8VtlVhhs5ycBUlXA7wHBWcBrjqnAw&sid=6ba85179e30d4fc2&spx=%2F&type=tcp&headerType=none#SG%2001").unwrap();
+
+        let outbound = server.to_outbound();
+        match server {
+            ServerType::Vless(server) => {
+                assert_eq!(server.name, "SG 01");
+                assert_eq!(server.address, "sg1.example.com");
+                assert_eq!(server.user_id, "5f1a6a8e-8d4c-4b6e-9d1f-2a3b4c5d6e7f");
+                assert_eq!(server.flow, "xtls-rprx-vision");
+                assert_eq!(server.security, "reality");
+                assert_eq!(server.sni, "www.microsoft.com");
+                assert_eq!(server.fingerprint, "chrome");
+                assert_eq!(server.short_id, "6ba85179e30d4fc2");
+                assert_eq!(server.spider_x, "/");
+            }
+            _ => panic!("expect vless server"),
+        }
+
+        assert_eq!(outbound.protocol, "vless");
+        let user = &outbound.settings.vnext.unwrap()[0].users[0];
+        assert_eq!(user.encryption, Some(String::from("none")));
+        assert_eq!(user.flow, Some(String::from("xtls-rprx-vision")));
+        assert_eq!(user.alter_id, None);
+        let stream_settings = outbound.stream_settings.unwrap();
+        assert_eq!(stream_settings.security, "reality");
+        assert_eq!(
+            stream_settings.reality_settings.unwrap().public_key,
+            "Z84J2IelR9ch3k8VtlVhhs5ycBUlXA7wHBWcBrjqnAw"
+        );
+        assert!(stream_settings.tls_settings.is_none());
+    }
+
+    #[test]
+    fn parse_vless_over_grpc_with_tls() {
+        let server = from_str("vless://uuid@jp.example.com:8443?security=tls&sni=jp.example.com&type=grpc&serviceName=grpc-svc#JP").unwrap();
+
+        let stream_settings = server.to_outbound().stream_settings.unwrap();
+        assert_eq!(stream_settings.network, "grpc");
+        assert_eq!(stream_settings.security, "tls");
+        assert_eq!(
+            stream_settings.tls_settings.unwrap().server_name,
+            "jp.example.com"
+        );
+        assert_eq!(
+            stream_settings.grpc_settings.unwrap().service_name,
+            "grpc-svc"
+        );
+    }
+
+    #[test]
+    fn parse_shadowsocks_sip002_with_plugin() {
+        let server = from_str("ss://YWVzLTI1Ni1nY206cGFzc3dvcmQ@198.51.100.1:8888/?plugin=v2ray-plugin%3Bmode%3Dwebsocket%3Btls%3Bhost%3Dcdn.example.com%3Bpath%3D%2Fss#Example%20SS").unwrap();
+
+        let outbound = server.to_outbound();
+        assert_eq!(
+            server,
+            ServerType::Shadowsocks(ShadowsocksServer {
+                name: String::from("Example SS"),
+                address: String::from("198.51.100.1"),
+                port: 8888,
+                method: String::from("aes-256-gcm"),
+                password: String::from("password"),
+                plugin: String::from("v2ray-plugin"),
+                plugin_opts: String::from("mode=websocket;tls;host=cdn.example.com;path=/ss"),
+            })
+        );
+
+        assert_eq!(outbound.protocol, "shadowsocks");
+        let ss = &outbound.settings.servers.unwrap()[0];
+        assert_eq!(ss.method, Some(String::from("aes-256-gcm")));
+        assert_eq!(ss.password, "password");
+        let stream_settings = outbound.stream_settings.unwrap();
+        assert_eq!(stream_settings.network, "ws");
+        assert_eq!(stream_settings.security, "tls");
+        let ws_settings = stream_settings.ws_settings.unwrap();
+        assert_eq!(ws_settings.path, "/ss");
+        assert_eq!(ws_settings.headers.host, "cdn.example.com");
+    }
+
+    #[test]
+    fn reject_shadowsocks_plugins_without_transport() {
+        for link in [
+            "ss://YWVzLTI1Ni1nY206cGFzc3dvcmQ@198.51.100.1:8888/?plugin=obfs-local%3Bobfs%3Dhttp#Obfs",
+            "ss://YWVzLTI1Ni1nY206cGFzc3dvcmQ@198.51.100.1:8888/?plugin=v2ray-plugin%3Bmode%3Dquic#Quic",
+        ] {
+            let err = from_str(link).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::ParseServerURLError));
+            assert!(err.message.contains("unsupported plugin"));
+        }
+
+        // websocket is the default mode of v2ray-plugin
+        assert!(from_str(
+            "ss://YWVzLTI1Ni1nY206cGFzc3dvcmQ@198.51.100.1:8888/?plugin=v2ray-plugin#WS"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn parse_shadowsocks_sip002_plain_user_info() {
+        let server = from_str("ss://2022-blake3-aes-128-gcm:YctPZ6U7xPPcU%2Bgp3u%2B0tx%2FtRizJN9K8y%2BuKlW2qjlI%3D@[2001:db8::2]:8443#2022").unwrap();
+
+        match server {
+            ServerType::Shadowsocks(server) => {
+                assert_eq!(server.method, "2022-blake3-aes-128-gcm");
+                assert_eq!(
+                    server.password,
+                    "YctPZ6U7xPPcU+gp3u+0tx/tRizJN9K8y+uKlW2qjlI="
+                );
+                assert_eq!(server.address, "2001:db8::2");
+                assert_eq!(server.port, 8443);
+            }
+            _ => panic!("expect shadowsocks server"),
+        }
+    }
+
+    #[test]
+    fn parse_shadowsocks_legacy() {
+        // base64 of chacha20-ietf-poly1305:p@ss:word@ss.example.com:443
+        let server = from_str(
+            "ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwQHNzOndvcmRAc3MuZXhhbXBsZS5jb206NDQz#Legacy",
+        )
+        .unwrap();
+
+        let outbound = server.to_outbound();
+        match server {
+            ServerType::Shadowsocks(server) => {
+                assert_eq!(server.name, "Legacy");
+                assert_eq!(server.method, "chacha20-ietf-poly1305");
+                assert_eq!(server.password, "p@ss:word");
+                assert_eq!(server.address, "ss.example.com");
+                assert_eq!(server.port, 443);
+                assert!(server.plugin.is_empty());
+            }
+            _ => panic!("expect shadowsocks server"),
+        }
+        assert_eq!(outbound.stream_settings.unwrap().network, "tcp");
+    }
+
+    #[test]
+    fn share_links_round_trip() {
+        let links = [
+            vmess_link(json!({
+                "v": "2", "ps": "HK 01", "add": "hk.example.com", "port": "443",
+                "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": "0", "scy": "auto",
+                "net": "ws", "type": "none", "host": "cdn.example.com", "path": "/vmess?ed=2048",
+                "tls": "tls", "sni": "sni.example.com", "alpn": "h2,http/1.1", "fp": "chrome"
+            })),
+            String::from("trojan://p%40ss@hk2.example.com:8443?allowInsecure=1&peer=cdn.example.com&type=ws&path=%2Fws&host=ws.example.com#HK%2002"),
+            String::from("trojan://31b98cae-da2d-4456-b351-f91838313f0a@jp1.lxjc.app:443?allowInsecure=0&peer=16-163-218-240.nhost.00cdn.com&sni=16-163-218-240.nhost.00cdn.com#%E5%89%A9%E4%BD%99%E6%B5%81%E9%87%8F%EF%BC%9A99.89%20GB"),
+            String::from("vless://5f1a6a8e-8d4c-4b6e-9d1f-2a3b4c5d6e7f@[2001:db8::1]:443?encryption=none&flow=xtls-rprx-vision&security=reality&sni=www.microsoft.com&fp=chrome&pbk=Z84J2IelR9ch3k8VtlVhhs5ycBUlXA7wHBWcBrjqnAw&sid=6ba85179e30d4fc2&spx=%2F&type=tcp#SG%2001"),
+            String::from("vless://uuid@jp.example.com:8443?security=tls&sni=jp.example.com&type=grpc&serviceName=grpc-svc#JP"),
+            String::from("ss://YWVzLTI1Ni1nY206cGFzc3dvcmQ@198.51.100.1:8888/?plugin=v2ray-plugin%3Bmode%3Dwebsocket%3Btls%3Bhost%3Dcdn.example.com#Example%20SS"),
+            String::from("ss://2022-blake3-aes-128-gcm:YctPZ6U7xPPcU%2Bgp3u%2B0tx%2FtRizJN9K8y%2BuKlW2qjlI%3D@198.51.100.2:8443#2022"),
+        ];
+
+        for link in links.iter() {
+            let server = from_str(link).unwrap();
+            let exported = server.to_url();
+            assert_eq!(from_str(exported.as_str()).unwrap(), server, "{}", exported);
+        }
+    }
+
+    #[test]
+    fn export_trojan_link() {
+        let server = ServerType::Trojan(TrojanServer {
+            name: String::from("JP 01"),
+            address: String::from("jp.example.com"),
+            port: 443,
+            password: String::from("secret"),
+            sni: String::from("jp.example.com"),
+            allow_insecure: false,
+            network: String::from("tcp"),
+            path: String::from(""),
+            host: String::from(""),
+        });
+
+        assert_eq!(
+            server.to_url(),
+            "trojan://secret@jp.example.com:443?sni=jp.example.com&type=tcp#JP%2001"
+        );
+    }
+
+    #[test]
+    fn vless_round_trips_through_yaml() {
+        let server = from_str("vless://uuid@us.example.com:443?security=tls&type=ws&path=%2Fray&host=cdn.example.com#US").unwrap();
+
+        let yaml = serde_yaml::to_string(&server).unwrap();
+        assert!(yaml.contains("type: vless"));
+        assert_eq!(serde_yaml::from_str::<ServerType>(&yaml).unwrap(), server);
+    }
+}
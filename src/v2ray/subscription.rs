@@ -0,0 +1,912 @@
+use serde::Deserialize;
+use serde_json::Value;
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::errors::kind::ErrorKind;
+use crate::errors::Error;
+use crate::v2ray::server;
+use crate::v2ray::server::ServerType;
+use crate::v2ray::server::ShadowsocksServer;
+use crate::v2ray::server::TrojanServer;
+use crate::v2ray::server::VlessServer;
+use crate::v2ray::server::VmessServer;
+
+/// Format of a subscription body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// base64 encoded share links, one per line
+    Base64,
+    /// plain share links, one per line
+    Links,
+    /// clash config with a `proxies` list
+    Clash,
+    /// SIP008 json document with a `servers` list
+    Sip008,
+    /// sing-box json config with an `outbounds` list
+    SingBox,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Base64 => write!(f, "base64"),
+            Format::Links => write!(f, "links"),
+            Format::Clash => write!(f, "clash"),
+            Format::Sip008 => write!(f, "sip008"),
+            Format::SingBox => write!(f, "sing-box"),
+        }
+    }
+}
+
+/// Detects the format of a subscription body from its content type and
+/// shape, and parses the servers in it.
+///
+/// Entries which cannot be parsed are reported and skipped.
+pub fn parse(content_type: &str, body: &str) -> Result<(Format, Vec<ServerType>), Error> {
+    let body = body.trim();
+
+    if content_type.contains("json") || body.starts_with('{') {
+        return parse_json(body);
+    }
+
+    // base64 bodies are sometimes wrapped into multiple lines
+    let compact: String = body.split_whitespace().collect();
+    if let Ok(links) = server::decode_base64(compact.as_str()) {
+        return Ok((Format::Base64, parse_links(links.as_str())));
+    }
+
+    if body.lines().any(|line| line.starts_with("proxies:")) {
+        return Ok((Format::Clash, parse_clash(body)?));
+    }
+
+    if body.lines().any(|line| line.contains("://")) {
+        return Ok((Format::Links, parse_links(body)));
+    }
+
+    Err(Error {
+        kind: ErrorKind::UnknownSubscriptionFormat,
+        message: String::from("unknown subscription format"),
+    })
+}
+
+fn parse_links(links: &str) -> Vec<ServerType> {
+    let mut servers = vec![];
+
+    for entry in links.lines() {
+        if entry.trim().is_empty() {
+            continue;
+        }
+
+        match server::from_str(entry.trim()) {
+            Ok(server) => servers.push(server),
+            Err(err) => {
+                println!(
+                    "parsing server entry: {} with error: {}",
+                    entry, err.message
+                );
+                continue;
+            }
+        }
+    }
+
+    servers
+}
+
+#[derive(Debug, Deserialize)]
+struct ClashConfig {
+    #[serde(default)]
+    proxies: Vec<serde_yaml::Value>,
+}
+
+#[serde_as]
+#[derive(Default, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct ClashProxy {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+    server: String,
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    port: i32,
+    uuid: String,
+    #[serde(rename = "alterId")]
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    alter_id: i32,
+    cipher: String,
+    password: String,
+    flow: String,
+    tls: bool,
+    sni: String,
+    servername: String,
+    skip_cert_verify: bool,
+    client_fingerprint: String,
+    alpn: Vec<String>,
+    network: String,
+    ws_opts: ClashWsOpts,
+    h2_opts: ClashH2Opts,
+    http_opts: ClashHttpOpts,
+    grpc_opts: ClashGrpcOpts,
+    reality_opts: Option<ClashRealityOpts>,
+    plugin: String,
+    plugin_opts: ClashPluginOpts,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct ClashWsOpts {
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct ClashH2Opts {
+    host: Vec<String>,
+    path: String,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct ClashHttpOpts {
+    path: Vec<String>,
+    headers: HashMap<String, Vec<String>>,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct ClashGrpcOpts {
+    grpc_service_name: String,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct ClashRealityOpts {
+    public_key: String,
+    short_id: String,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+struct ClashPluginOpts {
+    mode: String,
+    host: String,
+    path: String,
+    tls: bool,
+}
+
+impl ClashProxy {
+    /// Returns the transport network with its host and path, where path
+    /// holds the grpc service name for grpc
+    fn transport(&self) -> (String, String, String) {
+        match self.network.as_str() {
+            "ws" => (
+                String::from("ws"),
+                self.ws_opts
+                    .headers
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case("host"))
+                    .map(|(_, v)| v.clone())
+                    .unwrap_or_default(),
+                self.ws_opts.path.clone(),
+            ),
+            "h2" => (
+                String::from("h2"),
+                self.h2_opts.host.join(","),
+                self.h2_opts.path.clone(),
+            ),
+            "grpc" => (
+                String::from("grpc"),
+                String::from(""),
+                self.grpc_opts.grpc_service_name.clone(),
+            ),
+            // clash `http` network is tcp with http header obfuscation
+            "http" => (
+                String::from("tcp"),
+                self.http_opts
+                    .headers
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case("host"))
+                    .map(|(_, v)| v.join(","))
+                    .unwrap_or_default(),
+                self.http_opts.path.first().cloned().unwrap_or_default(),
+            ),
+            _ => (String::from("tcp"), String::from(""), String::from("")),
+        }
+    }
+
+    fn sni(&self) -> String {
+        match self.servername.is_empty() {
+            true => self.sni.clone(),
+            false => self.servername.clone(),
+        }
+    }
+
+    fn to_server(&self) -> Result<ServerType, Error> {
+        let (network, host, path) = self.transport();
+
+        match self.ty.as_str() {
+            "vmess" => Ok(ServerType::Vmess(VmessServer {
+                name: self.name.clone(),
+                header_type: match self.network.as_str() {
+                    "http" => String::from("http"),
+                    _ => String::from(""),
+                },
+                network,
+                user_id: self.uuid.clone(),
+                alter_id: self.alter_id,
+                address: self.server.clone(),
+                port: self.port,
+                cipher: self.cipher.clone(),
+                host,
+                path,
+                security: match self.tls {
+                    true => String::from("tls"),
+                    false => String::from(""),
+                },
+                sni: self.sni(),
+                alpn: self.alpn.join(","),
+                fingerprint: self.client_fingerprint.clone(),
+                allow_insecure: self.skip_cert_verify,
+            })),
+            "vless" => Ok(ServerType::Vless(VlessServer {
+                name: self.name.clone(),
+                address: self.server.clone(),
+                port: self.port,
+                user_id: self.uuid.clone(),
+                encryption: String::from("none"),
+                flow: self.flow.clone(),
+                security: match (&self.reality_opts, self.tls) {
+                    (Some(_), _) => String::from("reality"),
+                    (None, true) => String::from("tls"),
+                    (None, false) => String::from("none"),
+                },
+                sni: self.sni(),
+                allow_insecure: self.skip_cert_verify,
+                fingerprint: self.client_fingerprint.clone(),
+                public_key: self
+                    .reality_opts
+                    .as_ref()
+                    .map(|opts| opts.public_key.clone())
+                    .unwrap_or_default(),
+                short_id: self
+                    .reality_opts
+                    .as_ref()
+                    .map(|opts| opts.short_id.clone())
+                    .unwrap_or_default(),
+                spider_x: String::from(""),
+                service_name: match network.as_str() {
+                    "grpc" => path.clone(),
+                    _ => String::from(""),
+                },
+                network,
+                path,
+                host,
+            })),
+            "trojan" => Ok(ServerType::Trojan(TrojanServer {
+                name: self.name.clone(),
+                address: self.server.clone(),
+                port: self.port,
+                password: self.password.clone(),
+                sni: self.sni(),
+                allow_insecure: self.skip_cert_verify,
+                network,
+                path,
+                host,
+            })),
+            "ss" => {
+                let (plugin, plugin_opts) = match self.plugin.as_str() {
+                    "" => (String::from(""), String::from("")),
+                    "v2ray-plugin" => {
+                        let mut opts = vec![format!("mode={}", self.plugin_opts.mode)];
+                        if self.plugin_opts.tls {
+                            opts.push(String::from("tls"));
+                        }
+                        if !self.plugin_opts.host.is_empty() {
+                            opts.push(format!("host={}", self.plugin_opts.host));
+                        }
+                        if !self.plugin_opts.path.is_empty() {
+                            opts.push(format!("path={}", self.plugin_opts.path));
+                        }
+                        (String::from("v2ray-plugin"), opts.join(";"))
+                    }
+                    // v2ray has no transport for obfs and the other plugins
+                    plugin => {
+                        return Err(Error {
+                            kind: ErrorKind::ParseServerURLError,
+                            message: format!(
+                                "shadowsocks server {} uses unsupported plugin {}",
+                                self.name, plugin
+                            ),
+                        })
+                    }
+                };
+
+                let server = ShadowsocksServer {
+                    name: self.name.clone(),
+                    address: self.server.clone(),
+                    port: self.port,
+                    method: self.cipher.clone(),
+                    password: self.password.clone(),
+                    plugin,
+                    plugin_opts,
+                };
+                server.check_plugin()?;
+                Ok(ServerType::Shadowsocks(server))
+            }
+            _ => Err(Error {
+                kind: ErrorKind::UnknownServerProtocol,
+                message: format!("unknown server protocol: {}", self.ty),
+            }),
+        }
+    }
+}
+
+fn parse_clash(body: &str) -> Result<Vec<ServerType>, Error> {
+    let config: ClashConfig = match serde_yaml::from_str(body) {
+        Ok(config) => config,
+        Err(err) => {
+            return Err(Error {
+                kind: ErrorKind::ParseYAMLError,
+                message: format!("parse clash subscription err: {}", err),
+            })
+        }
+    };
+
+    let mut servers = vec![];
+    for value in config.proxies.into_iter() {
+        // a proxy v2ray can not use may not even deserialize, skip it alone
+        let proxy: ClashProxy = match serde_yaml::from_value(value) {
+            Ok(proxy) => proxy,
+            Err(err) => {
+                println!("parsing clash proxy err: {}", err);
+                continue;
+            }
+        };
+        match proxy.to_server() {
+            Ok(server) => servers.push(server),
+            Err(err) => {
+                println!(
+                    "parsing clash proxy: {} with error: {}",
+                    proxy.name, err.message
+                );
+                continue;
+            }
+        }
+    }
+
+    Ok(servers)
+}
+
+fn parse_json(body: &str) -> Result<(Format, Vec<ServerType>), Error> {
+    let document: Value = match serde_json::from_str(body) {
+        Ok(document) => document,
+        Err(err) => {
+            return Err(Error {
+                kind: ErrorKind::ParseJSONError,
+                message: format!("parse json subscription err: {}", err),
+            })
+        }
+    };
+
+    let (format, entries) = match (document.get("servers"), document.get("outbounds")) {
+        (Some(Value::Array(entries)), _) => (Format::Sip008, entries),
+        (_, Some(Value::Array(entries))) => (Format::SingBox, entries),
+        _ => {
+            return Err(Error {
+                kind: ErrorKind::UnknownSubscriptionFormat,
+                message: String::from("json subscription has neither servers nor outbounds"),
+            })
+        }
+    };
+
+    let mut servers = vec![];
+    for entry in entries.iter() {
+        let result = match format {
+            Format::Sip008 => serde_json::from_value::<Sip008Server>(entry.clone())
+                .map(|server| Some(server.to_server())),
+            _ => serde_json::from_value::<SingBoxOutbound>(entry.clone())
+                .map(|outbound| outbound.to_server()),
+        };
+
+        match result {
+            Ok(Some(Ok(server))) => servers.push(server),
+            // sing-box selectors, direct and block outbounds are not servers
+            Ok(None) => continue,
+            Ok(Some(Err(err))) => {
+                println!(
+                    "parsing {} entry: {} with error: {}",
+                    format, entry, err.message
+                )
+            }
+            Err(err) => println!("parsing {} entry: {} with error: {}", format, entry, err),
+        }
+    }
+
+    Ok((format, servers))
+}
+
+#[serde_as]
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct Sip008Server {
+    remarks: String,
+    server: String,
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    server_port: i32,
+    password: String,
+    method: String,
+    plugin: String,
+    plugin_opts: String,
+}
+
+impl Sip008Server {
+    fn to_server(&self) -> Result<ServerType, Error> {
+        let server = ShadowsocksServer {
+            name: match self.remarks.is_empty() {
+                true => format!("{}:{}", self.server, self.server_port),
+                false => self.remarks.clone(),
+            },
+            address: self.server.clone(),
+            port: self.server_port,
+            method: self.method.clone(),
+            password: self.password.clone(),
+            plugin: self.plugin.clone(),
+            plugin_opts: self.plugin_opts.clone(),
+        };
+        server.check_plugin()?;
+        Ok(ServerType::Shadowsocks(server))
+    }
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct SingBoxOutbound {
+    #[serde(rename = "type")]
+    ty: String,
+    tag: String,
+    server: String,
+    server_port: i32,
+    uuid: String,
+    alter_id: i32,
+    security: String,
+    password: String,
+    method: String,
+    flow: String,
+    plugin: String,
+    plugin_opts: String,
+    tls: SingBoxTls,
+    transport: SingBoxTransport,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct SingBoxTls {
+    enabled: bool,
+    server_name: String,
+    insecure: bool,
+    alpn: Vec<String>,
+    utls: SingBoxUtls,
+    reality: SingBoxReality,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct SingBoxUtls {
+    enabled: bool,
+    fingerprint: String,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct SingBoxReality {
+    enabled: bool,
+    public_key: String,
+    short_id: String,
+}
+
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+struct SingBoxTransport {
+    #[serde(rename = "type")]
+    ty: String,
+    path: String,
+    headers: HashMap<String, Value>,
+    host: Value,
+    service_name: String,
+}
+
+impl SingBoxOutbound {
+    /// Returns the transport network with its host and path, where path
+    /// holds the grpc service name for grpc
+    fn transport(&self) -> (String, String, String) {
+        let transport = &self.transport;
+        match transport.ty.as_str() {
+            "ws" => (
+                String::from("ws"),
+                transport
+                    .headers
+                    .iter()
+                    .find(|(k, _)| k.eq_ignore_ascii_case("host"))
+                    .and_then(|(_, v)| v.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+                transport.path.clone(),
+            ),
+            "http" => (
+                String::from("h2"),
+                match &transport.host {
+                    Value::String(host) => host.clone(),
+                    Value::Array(hosts) => hosts
+                        .iter()
+                        .filter_map(|host| host.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(","),
+                    _ => String::from(""),
+                },
+                transport.path.clone(),
+            ),
+            "grpc" => (
+                String::from("grpc"),
+                String::from(""),
+                transport.service_name.clone(),
+            ),
+            "quic" => (String::from("quic"), String::from(""), String::from("")),
+            _ => (String::from("tcp"), String::from(""), String::from("")),
+        }
+    }
+
+    fn fingerprint(&self) -> String {
+        match self.tls.utls.enabled {
+            true => self.tls.utls.fingerprint.clone(),
+            false => String::from(""),
+        }
+    }
+
+    /// Converts the outbound into a server, returns None for outbounds
+    /// which are not proxy servers
+    fn to_server(&self) -> Option<Result<ServerType, Error>> {
+        let (network, host, path) = self.transport();
+        let sni = match self.tls.server_name.is_empty() {
+            true => self.server.clone(),
+            false => self.tls.server_name.clone(),
+        };
+
+        let server = match self.ty.as_str() {
+            "vmess" => ServerType::Vmess(VmessServer {
+                name: self.tag.clone(),
+                network,
+                user_id: self.uuid.clone(),
+                alter_id: self.alter_id,
+                address: self.server.clone(),
+                port: self.server_port,
+                cipher: self.security.clone(),
+                header_type: String::from(""),
+                host,
+                path,
+                security: match self.tls.enabled {
+                    true => String::from("tls"),
+                    false => String::from(""),
+                },
+                sni,
+                alpn: self.tls.alpn.join(","),
+                fingerprint: self.fingerprint(),
+                allow_insecure: self.tls.insecure,
+            }),
+            "vless" => ServerType::Vless(VlessServer {
+                name: self.tag.clone(),
+                address: self.server.clone(),
+                port: self.server_port,
+                user_id: self.uuid.clone(),
+                encryption: String::from("none"),
+                flow: self.flow.clone(),
+                security: match (self.tls.reality.enabled, self.tls.enabled) {
+                    (true, _) => String::from("reality"),
+                    (false, true) => String::from("tls"),
+                    (false, false) => String::from("none"),
+                },
+                sni,
+                allow_insecure: self.tls.insecure,
+                fingerprint: self.fingerprint(),
+                public_key: self.tls.reality.public_key.clone(),
+                short_id: self.tls.reality.short_id.clone(),
+                spider_x: String::from(""),
+                service_name: match network.as_str() {
+                    "grpc" => path.clone(),
+                    _ => String::from(""),
+                },
+                network,
+                path,
+                host,
+            }),
+            "trojan" => ServerType::Trojan(TrojanServer {
+                name: self.tag.clone(),
+                address: self.server.clone(),
+                port: self.server_port,
+                password: self.password.clone(),
+                sni,
+                allow_insecure: self.tls.insecure,
+                network,
+                path,
+                host,
+            }),
+            "shadowsocks" => {
+                let server = ShadowsocksServer {
+                    name: self.tag.clone(),
+                    address: self.server.clone(),
+                    port: self.server_port,
+                    method: self.method.clone(),
+                    password: self.password.clone(),
+                    plugin: self.plugin.clone(),
+                    plugin_opts: self.plugin_opts.clone(),
+                };
+                if let Err(err) = server.check_plugin() {
+                    return Some(Err(err));
+                }
+                ServerType::Shadowsocks(server)
+            }
+            "direct" | "block" | "dns" | "selector" | "urltest" => return None,
+            _ => {
+                return Some(Err(Error {
+                    kind: ErrorKind::UnknownServerProtocol,
+                    message: format!("unknown server protocol: {}", self.ty),
+                }))
+            }
+        };
+
+        Some(Ok(server))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLASH: &str = r#"
+port: 7890
+mode: rule
+proxies:
+  - name: "HK vmess"
+    type: vmess
+    server: hk.example.com
+    port: 443
+    uuid: b831381d-6324-4d53-ad4f-8cda48b30811
+    alterId: 0
+    cipher: auto
+    udp: true
+    tls: true
+    skip-cert-verify: false
+    servername: cdn.example.com
+    network: ws
+    ws-opts:
+      path: /vmess
+      headers:
+        Host: cdn.example.com
+  - name: "SG vless"
+    type: vless
+    server: sg.example.com
+    port: "443"
+    uuid: 5f1a6a8e-8d4c-4b6e-9d1f-2a3b4c5d6e7f
+    flow: xtls-rprx-vision
+    tls: true
+    servername: www.microsoft.com
+    client-fingerprint: chrome
+    reality-opts:
+      public-key: Z84J2IelR9ch3k8VtlVhhs5ycBUlXA7wHBWcBrjqnAw
+      short-id: 6ba85179e30d4fc2
+  - {name: "JP trojan", type: trojan, server: jp.example.com, port: 443, password: secret, sni: jp.example.com, skip-cert-verify: true}
+  - name: "US ss"
+    type: ss
+    server: us.example.com
+    port: 8388
+    cipher: aes-256-gcm
+    password: password
+    plugin: v2ray-plugin
+    plugin-opts:
+      mode: websocket
+      host: bing.com
+  - name: "US obfs"
+    type: ss
+    server: us.example.com
+    port: 8389
+    cipher: aes-256-gcm
+    password: password
+    plugin: obfs
+    plugin-opts:
+      mode: http
+      host: bing.com
+  - {name: "TW ssr", type: ssr, server: tw.example.com, port: 443, cipher: none, password: x, protocol: origin, obfs: plain}
+proxy-groups:
+  - name: Proxy
+    type: select
+    proxies: ["HK vmess"]
+rules:
+  - MATCH,Proxy
+"#;
+
+    #[test]
+    fn parse_clash_proxies() {
+        let (format, servers) = parse("text/plain", CLASH).unwrap();
+
+        assert_eq!(format, Format::Clash);
+        assert_eq!(servers.len(), 4);
+
+        match &servers[0] {
+            ServerType::Vmess(server) => {
+                assert_eq!(server.name, "HK vmess");
+                assert_eq!(server.network, "ws");
+                assert_eq!(server.host, "cdn.example.com");
+                assert_eq!(server.path, "/vmess");
+                assert_eq!(server.security, "tls");
+                assert_eq!(server.sni, "cdn.example.com");
+            }
+            _ => panic!("expect vmess server"),
+        }
+        match &servers[1] {
+            ServerType::Vless(server) => {
+                assert_eq!(server.port, 443);
+                assert_eq!(server.security, "reality");
+                assert_eq!(server.short_id, "6ba85179e30d4fc2");
+                assert_eq!(server.fingerprint, "chrome");
+            }
+            _ => panic!("expect vless server"),
+        }
+        match &servers[2] {
+            ServerType::Trojan(server) => {
+                assert_eq!(server.password, "secret");
+                assert!(server.allow_insecure);
+                assert_eq!(server.network, "tcp");
+            }
+            _ => panic!("expect trojan server"),
+        }
+        match &servers[3] {
+            ServerType::Shadowsocks(server) => {
+                assert_eq!(server.method, "aes-256-gcm");
+                assert_eq!(server.plugin, "v2ray-plugin");
+                assert_eq!(server.plugin_opts, "mode=websocket;host=bing.com");
+            }
+            _ => panic!("expect shadowsocks server"),
+        }
+    }
+
+    #[test]
+    fn parse_clash_skips_malformed_proxies() {
+        let body = r#"
+proxies:
+  - name: "HY2"
+    type: hysteria2
+    server: hy.example.com
+    port: 443
+    alpn: h3
+  - name: "JP trojan"
+    type: trojan
+    server: jp.example.com
+    port: 443
+    password: secret
+"#;
+        let (format, servers) = parse("text/plain", body).unwrap();
+
+        assert_eq!(format, Format::Clash);
+        assert_eq!(servers.len(), 1);
+        match &servers[0] {
+            ServerType::Trojan(server) => assert_eq!(server.name, "JP trojan"),
+            _ => panic!("expect trojan server"),
+        }
+    }
+
+    #[test]
+    fn parse_base64_links() {
+        let links = "trojan://secret@jp.example.com:443#JP\r\nss://YWVzLTI1Ni1nY206cGFzc3dvcmQ@198.51.100.1:8888#SS\r\n";
+        let (format, servers) = parse("", base64::encode(links).as_str()).unwrap();
+
+        assert_eq!(format, Format::Base64);
+        assert_eq!(servers.len(), 2);
+    }
+
+    #[test]
+    fn parse_plain_links() {
+        let links = "trojan://secret@jp.example.com:443#JP\nunknown://whatever\n";
+        let (format, servers) = parse("text/plain; charset=utf-8", links).unwrap();
+
+        assert_eq!(format, Format::Links);
+        assert_eq!(servers.len(), 1);
+    }
+
+    #[test]
+    fn parse_sip008() {
+        let body = r#"{
+            "version": 1,
+            "servers": [
+                {
+                    "id": "27b8a625-4f4b-4428-9f0f-8a2317db7c79",
+                    "remarks": "Server 1",
+                    "server": "198.51.100.1",
+                    "server_port": 8388,
+                    "password": "example",
+                    "method": "chacha20-ietf-poly1305",
+                    "plugin": "v2ray-plugin",
+                    "plugin_opts": "mode=websocket;host=cdn.example.com"
+                },
+                {"server": "198.51.100.2", "server_port": "8389", "password": "p", "method": "aes-128-gcm"}
+            ],
+            "bytes_used": 274877906944,
+            "bytes_remaining": 824633720832
+        }"#;
+        let (format, servers) = parse("application/json", body).unwrap();
+
+        assert_eq!(format, Format::Sip008);
+        assert_eq!(
+            servers[0],
+            ServerType::Shadowsocks(ShadowsocksServer {
+                name: String::from("Server 1"),
+                address: String::from("198.51.100.1"),
+                port: 8388,
+                method: String::from("chacha20-ietf-poly1305"),
+                password: String::from("example"),
+                plugin: String::from("v2ray-plugin"),
+                plugin_opts: String::from("mode=websocket;host=cdn.example.com"),
+            })
+        );
+        assert_eq!(servers[1].name(), "198.51.100.2:8389");
+    }
+
+    #[test]
+    fn parse_sing_box() {
+        let body = r#"{
+            "log": {"level": "info"},
+            "outbounds": [
+                {"type": "selector", "tag": "proxy", "outbounds": ["HK"]},
+                {
+                    "type": "vmess", "tag": "HK", "server": "hk.example.com", "server_port": 443,
+                    "uuid": "b831381d-6324-4d53-ad4f-8cda48b30811", "security": "auto", "alter_id": 0,
+                    "tls": {"enabled": true, "server_name": "cdn.example.com", "utls": {"enabled": true, "fingerprint": "chrome"}},
+                    "transport": {"type": "ws", "path": "/vmess", "headers": {"Host": "cdn.example.com"}}
+                },
+                {
+                    "type": "vless", "tag": "SG", "server": "sg.example.com", "server_port": 443,
+                    "uuid": "5f1a6a8e-8d4c-4b6e-9d1f-2a3b4c5d6e7f", "flow": "xtls-rprx-vision",
+                    "tls": {"enabled": true, "server_name": "www.microsoft.com",
+                            "reality": {"enabled": true, "public_key": "pbk", "short_id": "sid"}}
+                },
+                {"type": "trojan", "tag": "JP", "server": "jp.example.com", "server_port": 443, "password": "secret",
+                 "tls": {"enabled": true, "insecure": true}},
+                {"type": "hysteria2", "tag": "H2", "server": "h2.example.com", "server_port": 443, "password": "x"},
+                {"type": "direct", "tag": "direct"}
+            ]
+        }"#;
+        let (format, servers) = parse("text/plain", body).unwrap();
+
+        assert_eq!(format, Format::SingBox);
+        assert_eq!(servers.len(), 3);
+        match &servers[0] {
+            ServerType::Vmess(server) => {
+                assert_eq!(server.name, "HK");
+                assert_eq!(server.network, "ws");
+                assert_eq!(server.host, "cdn.example.com");
+                assert_eq!(server.fingerprint, "chrome");
+                assert_eq!(server.security, "tls");
+            }
+            _ => panic!("expect vmess server"),
+        }
+        match &servers[1] {
+            ServerType::Vless(server) => {
+                assert_eq!(server.security, "reality");
+                assert_eq!(server.public_key, "pbk");
+            }
+            _ => panic!("expect vless server"),
+        }
+        match &servers[2] {
+            ServerType::Trojan(server) => {
+                assert_eq!(server.sni, "jp.example.com");
+                assert!(server.allow_insecure);
+            }
+            _ => panic!("expect trojan server"),
+        }
+    }
+
+    #[test]
+    fn parse_unknown_format() {
+        assert!(parse("text/html", "<html>not found</html>").is_err());
+    }
+}
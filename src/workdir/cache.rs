@@ -0,0 +1,97 @@
+use crate::errors::kind::ErrorKind;
+use crate::errors::Error;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use super::dir::Dir;
+
+/// Last good response of a subscription, kept in `<workdir>/subscriptions`
+/// so that unchanged subscriptions are not rebuilt and failed requests can
+/// fall back to it
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubscriptionCache {
+    #[serde(default)]
+    pub content_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    pub body: String,
+}
+
+impl SubscriptionCache {
+    pub fn load(workdir: &Dir, name: &str) -> Option<SubscriptionCache> {
+        let content = fs::read_to_string(cache_filepath(workdir, name)).ok()?;
+        serde_yaml::from_str(content.as_str()).ok()
+    }
+
+    pub fn save(&self, workdir: &Dir, name: &str) -> Result<(), Error> {
+        let result = fs::create_dir_all(workdir.filepath("subscriptions"));
+        if result.is_err() {
+            return Err(Error {
+                kind: ErrorKind::CreateFileError,
+                message: format!(
+                    "create subscriptions cache dir err: {}",
+                    result.err().unwrap()
+                ),
+            });
+        }
+
+        let result = serde_yaml::to_string(self);
+        if result.is_err() {
+            return Err(Error {
+                kind: ErrorKind::EncodeYAMLError,
+                message: format!("encode subscription cache err: {}", result.err().unwrap()),
+            });
+        }
+
+        match fs::write(cache_filepath(workdir, name), result.unwrap()) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error {
+                kind: ErrorKind::WriteFileError,
+                message: format!("write subscription cache of {} err: {}", name, err),
+            }),
+        }
+    }
+
+    pub fn remove(workdir: &Dir, name: &str) {
+        let _ = fs::remove_file(cache_filepath(workdir, name));
+    }
+
+    pub fn rename(workdir: &Dir, name: &str, new_name: &str) {
+        let _ = fs::rename(
+            cache_filepath(workdir, name),
+            cache_filepath(workdir, new_name),
+        );
+    }
+}
+
+/// Characters kept as they are in cache file names, `%` is encoded so that
+/// every subscription name maps to its own file
+const FILENAME: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_');
+
+fn cache_filepath(workdir: &Dir, name: &str) -> String {
+    workdir.filepath(format!("subscriptions/{}", cache_filename(name)).as_str())
+}
+
+fn cache_filename(name: &str) -> String {
+    format!("{}.yaml", utf8_percent_encode(name, FILENAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_filenames_do_not_collide() {
+        let names = ["a b", "a.b", "a_b", "a%20b", "a/b", "机场"];
+        let filenames: Vec<String> = names.iter().map(|name| cache_filename(name)).collect();
+
+        assert_eq!(filenames[2], "a_b.yaml");
+        assert!(filenames.iter().all(|filename| !filename.contains('/')));
+        for (i, filename) in filenames.iter().enumerate() {
+            assert!(!filenames[..i].contains(filename), "{}", filename);
+        }
+    }
+}
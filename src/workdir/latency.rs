@@ -0,0 +1,135 @@
+use crate::errors::kind::ErrorKind;
+use crate::errors::Error;
+use crate::v2ray::server::ServerIdentity;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use super::dir::Dir;
+
+/// Latest latency test result of each server, kept in
+/// `<workdir>/latency.yaml` so that later runs can show them
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Latencies {
+    #[serde(default)]
+    results: Vec<Latency>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Latency {
+    pub identity: ServerIdentity,
+    pub tested_at: chrono::DateTime<chrono::Local>,
+    /// tcp connect time in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<u64>,
+    /// tls handshake time in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<u64>,
+    /// time of a request through a v2ray instance using the server, in
+    /// milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delay: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Latency {
+    pub fn new(identity: ServerIdentity) -> Latency {
+        Latency {
+            identity,
+            tested_at: chrono::Local::now(),
+            tcp: None,
+            tls: None,
+            delay: None,
+            error: None,
+        }
+    }
+
+    /// Short description shown next to server names
+    pub fn label(&self) -> String {
+        let mut parts = vec![];
+        if let Some(tcp) = self.tcp {
+            parts.push(format!("tcp {} ms", tcp));
+        }
+        if let Some(tls) = self.tls {
+            parts.push(format!("tls {} ms", tls));
+        }
+        if let Some(delay) = self.delay {
+            parts.push(format!("delay {} ms", delay));
+        }
+        if self.error.is_some() {
+            parts.push(String::from("failed"));
+        }
+        parts.join(", ")
+    }
+}
+
+impl Latencies {
+    pub fn load(workdir: &Dir) -> Latencies {
+        fs::read_to_string(workdir.filepath("latency.yaml"))
+            .ok()
+            .and_then(|content| serde_yaml::from_str(content.as_str()).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, workdir: &Dir) -> Result<(), Error> {
+        let result = serde_yaml::to_string(self);
+        if result.is_err() {
+            return Err(Error {
+                kind: ErrorKind::EncodeYAMLError,
+                message: format!("encode latency results err: {}", result.err().unwrap()),
+            });
+        }
+
+        match fs::write(workdir.filepath("latency.yaml"), result.unwrap()) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error {
+                kind: ErrorKind::WriteFileError,
+                message: format!("write latency.yaml err: {}", err),
+            }),
+        }
+    }
+
+    pub fn get(&self, identity: &ServerIdentity) -> Option<&Latency> {
+        self.results.iter().find(|l| l.identity.eq(identity))
+    }
+
+    /// Replaces the result of the same server
+    pub fn set(&mut self, latency: Latency) {
+        match self
+            .results
+            .iter_mut()
+            .find(|l| l.identity.eq(&latency.identity))
+        {
+            Some(result) => *result = latency,
+            None => self.results.push(latency),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2ray::server::from_str;
+
+    #[test]
+    fn set_replaces_result_of_same_server() {
+        let identity = from_str("trojan://p@hk.example.com:443#HK")
+            .unwrap()
+            .identity();
+        let mut latencies = Latencies::default();
+
+        let mut latency = Latency::new(identity.clone());
+        latency.tcp = Some(30);
+        latencies.set(latency);
+        let mut latency = Latency::new(identity.clone());
+        latency.tcp = Some(40);
+        latency.tls = Some(90);
+        latencies.set(latency);
+
+        assert_eq!(latencies.results.len(), 1);
+        assert_eq!(
+            latencies.get(&identity).unwrap().label(),
+            "tcp 40 ms, tls 90 ms"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use crate::errors::kind::ErrorKind;
+use crate::errors::Error;
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+
+use super::dir::Dir;
+
+/// Exclusive lock on `<workdir>/workdir.lock`, held while settings.yaml,
+/// servers.yaml, selected.yaml, latency.yaml or v2ray.json are loaded,
+/// changed and saved, so that the cli and the worker do not overwrite each
+/// other's changes. The lock is released when dropped.
+pub struct WorkdirLock {
+    file: File,
+}
+
+impl WorkdirLock {
+    /// Waits until no other process holds the lock
+    pub fn acquire(workdir: &Dir) -> Result<WorkdirLock, Error> {
+        match lock(workdir, FlockArg::LockExclusive)? {
+            Some(lock) => Ok(lock),
+            None => Err(Error {
+                kind: ErrorKind::LockWorkdirError,
+                message: String::from("workdir is locked by another process"),
+            }),
+        }
+    }
+
+    /// Returns None instead of waiting when another process holds the lock
+    pub fn try_acquire(workdir: &Dir) -> Result<Option<WorkdirLock>, Error> {
+        lock(workdir, FlockArg::LockExclusiveNonblock)
+    }
+}
+
+fn lock(workdir: &Dir, arg: FlockArg) -> Result<Option<WorkdirLock>, Error> {
+    let path = workdir.filepath("workdir.lock");
+    let file = match OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+    {
+        Ok(file) => file,
+        Err(err) => {
+            return Err(Error {
+                kind: ErrorKind::LockWorkdirError,
+                message: format!("open {} err: {}", path, err),
+            })
+        }
+    };
+
+    match flock(file.as_raw_fd(), arg) {
+        Ok(_) => Ok(Some(WorkdirLock { file })),
+        Err(Errno::EWOULDBLOCK) => Ok(None),
+        Err(err) => Err(Error {
+            kind: ErrorKind::LockWorkdirError,
+            message: format!("lock {} err: {}", path, err),
+        }),
+    }
+}
+
+impl Drop for WorkdirLock {
+    fn drop(&mut self) {
+        let _ = flock(self.file.as_raw_fd(), FlockArg::Unlock);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lock_is_exclusive() {
+        let path = std::env::temp_dir().join(format!("v2up-lock-{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        let workdir = Dir::new(String::from(path.to_str().unwrap())).unwrap();
+
+        let lock = WorkdirLock::acquire(&workdir).unwrap();
+        assert!(WorkdirLock::try_acquire(&workdir).unwrap().is_none());
+        let (sender, receiver) = mpsc::channel();
+        let dir = String::from(path.to_str().unwrap());
+        let waiter = thread::spawn(move || {
+            let workdir = Dir::new(dir).unwrap();
+            let _lock = WorkdirLock::acquire(&workdir).unwrap();
+            sender.send(()).unwrap();
+        });
+
+        assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(lock);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        waiter.join().unwrap();
+        assert!(WorkdirLock::try_acquire(&workdir).unwrap().is_some());
+
+        let _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -1,4 +1,8 @@
+pub mod cache;
 pub mod dir;
 mod file;
+pub mod latency;
+pub mod lock;
+pub mod selection;
 pub mod servers;
 pub mod settings;
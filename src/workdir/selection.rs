@@ -0,0 +1,184 @@
+use crate::errors::kind::ErrorKind;
+use crate::errors::Error;
+use crate::v2ray::config::Strategy;
+use crate::v2ray::server::ServerType;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use super::dir::Dir;
+use super::servers::Server;
+use super::servers::Servers;
+
+/// The server last written to v2ray.json, kept in `<workdir>/selected.yaml`
+/// so that it can be found again after its subscription is refreshed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Selection {
+    pub group: String,
+    pub server: ServerType,
+    /// the servers v2ray balances across along with this one, empty when
+    /// it is used alone
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub balanced: Vec<Selection>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<Strategy>,
+}
+
+impl Selection {
+    pub fn new(server: &Server) -> Selection {
+        Selection {
+            group: server.group.clone(),
+            server: server.server.clone(),
+            balanced: vec![],
+            strategy: None,
+        }
+    }
+
+    /// Selects several servers at once, the first one is kept at the top
+    /// level so that single server selections read the same
+    pub fn balance(servers: &[Server], strategy: Strategy) -> Result<Selection, Error> {
+        let mut selection = match servers.first() {
+            Some(server) => Selection::new(server),
+            None => {
+                return Err(Error {
+                    kind: ErrorKind::ServerNotFound,
+                    message: String::from("no servers to select"),
+                })
+            }
+        };
+        if servers.len() > 1 {
+            selection.balanced = servers[1..].iter().map(Selection::new).collect();
+            selection.strategy = Some(strategy);
+        }
+        Ok(selection)
+    }
+
+    /// Every selected server, each without the balanced ones
+    pub fn members(&self) -> Vec<Selection> {
+        let mut first = self.clone();
+        first.balanced = vec![];
+        first.strategy = None;
+
+        let mut members = vec![first];
+        members.extend(self.balanced.iter().cloned());
+        members
+    }
+
+    pub fn load(workdir: &Dir) -> Option<Selection> {
+        let content = fs::read_to_string(workdir.filepath("selected.yaml")).ok()?;
+        serde_yaml::from_str(content.as_str()).ok()
+    }
+
+    pub fn save(&self, workdir: &Dir) -> Result<(), Error> {
+        let result = serde_yaml::to_string(self);
+        if result.is_err() {
+            return Err(Error {
+                kind: ErrorKind::EncodeYAMLError,
+                message: format!("encode selected server err: {}", result.err().unwrap()),
+            });
+        }
+
+        match fs::write(workdir.filepath("selected.yaml"), result.unwrap()) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error {
+                kind: ErrorKind::WriteFileError,
+                message: format!("write selected.yaml err: {}", err),
+            }),
+        }
+    }
+
+    /// Finds the selected server among `servers`, by identity first and
+    /// then by name within the group, as providers rotate ports and
+    /// credentials but usually keep the names
+    pub fn locate<'a>(&self, servers: &'a Servers) -> Option<&'a Server> {
+        let identity = self.server.identity();
+        let in_group = |s: &&Server| s.group.eq(&self.group);
+
+        servers
+            .iter()
+            .filter(in_group)
+            .find(|s| s.server.identity() == identity)
+            .or_else(|| servers.iter().find(|s| s.server.identity() == identity))
+            .or_else(|| {
+                servers
+                    .iter()
+                    .filter(in_group)
+                    .find(|s| s.server.name() == self.server.name())
+            })
+    }
+
+    /// Whether `server` is exactly the selected one
+    pub fn is(&self, server: &Server) -> bool {
+        self.group == server.group && self.server == server.server
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2ray::server::from_str;
+
+    fn servers(links: &[(&str, &str)]) -> Servers {
+        let servers: Vec<Server> = links
+            .iter()
+            .map(|(group, link)| Server {
+                group: String::from(*group),
+                server: from_str(link).unwrap(),
+            })
+            .collect();
+        let yaml = serde_yaml::to_string(&serde_json::json!({ "servers": servers })).unwrap();
+        serde_yaml::from_str(yaml.as_str()).unwrap()
+    }
+
+    #[test]
+    fn locate_selected_server() {
+        let selection = Selection::new(&Server {
+            group: String::from("a"),
+            server: from_str("trojan://p@hk.example.com:443#HK").unwrap(),
+        });
+
+        // unchanged
+        let found = servers(&[
+            ("b", "trojan://p@hk.example.com:443#HK"),
+            ("a", "trojan://p@hk.example.com:443#HK"),
+        ]);
+        let server = selection.locate(&found).unwrap();
+        assert_eq!(server.group, "a");
+        assert!(selection.is(server));
+
+        // same identity, other parameters
+        let found = servers(&[("a", "trojan://p@hk.example.com:443?sni=x.com#HK%20new")]);
+        let server = selection.locate(&found).unwrap();
+        assert!(!selection.is(server));
+
+        // same name, rotated port
+        let found = servers(&[("a", "trojan://p@hk.example.com:8443#HK")]);
+        assert_eq!(selection.locate(&found).unwrap().server.port(), 8443);
+
+        // gone
+        let found = servers(&[("a", "trojan://p@jp.example.com:443#JP")]);
+        assert!(selection.locate(&found).is_none());
+    }
+
+    #[test]
+    fn balanced_selection() {
+        let found = servers(&[
+            ("a", "trojan://p@hk.example.com:443#HK"),
+            ("b", "trojan://p@jp.example.com:443#JP"),
+        ]);
+        let all: Vec<Server> = found.iter().cloned().collect();
+
+        let selection = Selection::balance(&all, Strategy::LeastPing).unwrap();
+        let yaml = serde_yaml::to_string(&selection).unwrap();
+        assert_eq!(serde_yaml::from_str::<Selection>(&yaml).unwrap(), selection);
+
+        let members = selection.members();
+        assert_eq!(members.len(), 2);
+        assert!(members[0].is(&all[0]) && members[0].balanced.is_empty());
+        assert!(members[1].is(&all[1]));
+
+        let single = Selection::balance(&all[..1], Strategy::LeastPing).unwrap();
+        assert_eq!(single, Selection::new(&all[0]));
+
+        assert!(Selection::balance(&[], Strategy::LeastPing).is_err());
+    }
+}
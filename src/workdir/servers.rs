@@ -1,14 +1,18 @@
 use crate::errors::kind::ErrorKind;
 use crate::errors::Error;
+use crate::v2ray::server::ServerIdentity;
 use crate::v2ray::server::ServerType;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
 use std::fs::OpenOptions;
 
 use super::dir::Dir;
+use super::settings::ServerFilter;
 use std::fs::File;
 use std::slice::Iter;
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Server {
     pub group: String,
     pub server: ServerType,
@@ -39,44 +43,110 @@ impl Servers {
         }
     }
 
+    /// Replaces the servers of the group by the filtered `servers` when
+    /// they differ from the stored ones. Returns None and keeps the group
+    /// when nothing is left after filtering. With `skip_duplicates`, servers
+    /// already provided by another group are left out.
     pub fn update_by_group_name(
         &mut self,
         group: &str,
         servers: &Vec<ServerType>,
-    ) -> Result<bool, Error> {
+        filter: &ServerFilter,
+        skip_duplicates: bool,
+    ) -> Result<Option<ServerDiff>, Error> {
+        let mut servers = filter.apply(servers)?;
+
         // if new servers is empty, then do nothing
         if servers.is_empty() {
-            return Ok(false);
+            return Ok(None);
         }
 
-        // 1. remove all servers with group name
-        let mut indices_to_remove: Vec<usize> = vec![];
-        for (idx, s) in self.servers.iter().enumerate() {
-            if !s.group.eq(group) {
-                continue;
-            }
-            indices_to_remove.push(idx);
+        let mut skipped = vec![];
+        if skip_duplicates {
+            let mut seen: HashSet<ServerIdentity> = self
+                .servers
+                .iter()
+                .filter(|s| !s.group.eq(group))
+                .map(|s| s.server.identity())
+                .collect();
+            let (kept, duplicates) = servers
+                .into_iter()
+                .partition(|server| seen.insert(server.identity()));
+            servers = kept;
+            skipped = duplicates;
         }
 
-        for i in indices_to_remove.iter().rev() {
-            self.servers.remove(*i);
+        let old: Vec<&ServerType> = self
+            .servers
+            .iter()
+            .filter(|s| s.group.eq(group))
+            .map(|s| &s.server)
+            .collect();
+        let mut diff = ServerDiff::new(&old, &servers);
+        diff.skipped = skipped;
+        if diff.is_empty() {
+            return Ok(Some(diff));
         }
 
+        // 1. remove all servers with group name
+        self.servers.retain(|s| !s.group.eq(group));
+
         // 2. add new servers
-        for server in servers.iter() {
+        for server in servers.into_iter() {
             self.servers.push(Server {
                 group: String::from(group),
-                server: (*server).clone(),
+                server,
             })
         }
 
-        Ok(true)
+        Ok(Some(diff))
+    }
+
+    /// Indices of servers whose identity already appeared earlier in the list
+    pub fn duplicates(&self) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        self.servers
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !seen.insert(s.server.identity()))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Removes the duplicates, keeping the first server of each identity,
+    /// returns the removed servers
+    pub fn dedupe(&mut self) -> Vec<Server> {
+        let mut removed = vec![];
+        for idx in self.duplicates().into_iter().rev() {
+            removed.insert(0, self.servers.remove(idx));
+        }
+        removed
+    }
+
+    /// Removes all servers of the group, returns the number of removed servers
+    pub fn remove_group(&mut self, group: &str) -> usize {
+        let count = self.servers.len();
+        self.servers.retain(|s| !s.group.eq(group));
+        count - self.servers.len()
+    }
+
+    pub fn rename_group(&mut self, group: &str, new_group: &str) {
+        for server in self.servers.iter_mut() {
+            if server.group.eq(group) {
+                server.group = String::from(new_group);
+            }
+        }
+    }
+
+    pub fn count_by_group(&self, group: &str) -> usize {
+        self.servers.iter().filter(|s| s.group.eq(group)).count()
     }
 
     pub fn save(&self) -> Result<(), Error> {
         // let servers_file = File::open(self.filepath.as_str()).expect("fail to open servers.yaml");
         let servers_file = OpenOptions::new()
             .write(true)
+            .truncate(true)
             .open(self.filepath.as_str())
             .expect("fail to open servers.yaml");
         serde_yaml::to_writer(servers_file, self)
@@ -92,3 +162,219 @@ impl Servers {
         self.servers.get(idx)
     }
 }
+
+/// Changes between two server lists, servers are matched by identity
+#[derive(Debug, Default)]
+pub struct ServerDiff {
+    pub added: Vec<ServerType>,
+    pub removed: Vec<ServerType>,
+    /// (old, new) pairs with the same identity but other differences
+    pub modified: Vec<(ServerType, ServerType)>,
+    /// new servers left out as another group provides them
+    pub skipped: Vec<ServerType>,
+}
+
+impl ServerDiff {
+    pub fn new(old: &[&ServerType], new: &[ServerType]) -> ServerDiff {
+        let mut diff = ServerDiff::default();
+        let mut unmatched: Vec<&ServerType> = old.to_vec();
+
+        for server in new.iter() {
+            let identity = server.identity();
+            match unmatched.iter().position(|s| s.identity() == identity) {
+                Some(idx) => {
+                    let old = unmatched.remove(idx);
+                    if old != server {
+                        diff.modified.push((old.clone(), server.clone()));
+                    }
+                }
+                None => diff.added.push(server.clone()),
+            }
+        }
+        diff.removed = unmatched.into_iter().cloned().collect();
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl fmt::Display for ServerDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} removed, {} modified",
+            self.added.len(),
+            self.removed.len(),
+            self.modified.len()
+        )?;
+        if !self.skipped.is_empty() {
+            write!(f, ", {} duplicates skipped", self.skipped.len())?;
+        }
+        for server in self.added.iter() {
+            write!(f, "\n  + {} ({})", server.name(), server.identity())?;
+        }
+        for server in self.removed.iter() {
+            write!(f, "\n  - {} ({})", server.name(), server.identity())?;
+        }
+        for (old, new) in self.modified.iter() {
+            match old.name() == new.name() {
+                true => write!(f, "\n  ~ {} ({})", new.name(), new.identity())?,
+                false => write!(
+                    f,
+                    "\n  ~ {} -> {} ({})",
+                    old.name(),
+                    new.name(),
+                    new.identity()
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v2ray::server::from_str;
+
+    fn trojan(link: &str) -> ServerType {
+        from_str(link).unwrap()
+    }
+
+    #[test]
+    fn update_group_reports_diff() {
+        let mut servers = Servers {
+            filepath: String::new(),
+            servers: vec![],
+        };
+        let filter = ServerFilter::default();
+        let first = vec![
+            trojan("trojan://p1@a.example.com:443#A"),
+            trojan("trojan://p2@b.example.com:443#B"),
+        ];
+
+        let diff = servers
+            .update_by_group_name("g", &first, &filter, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(diff.added.len(), 2);
+
+        let diff = servers
+            .update_by_group_name("g", &first, &filter, false)
+            .unwrap()
+            .unwrap();
+        assert!(diff.is_empty());
+
+        let second = vec![
+            trojan("trojan://p1@a.example.com:443#A%20renamed"),
+            trojan("trojan://p3@c.example.com:443#C"),
+        ];
+        let diff = servers
+            .update_by_group_name("g", &second, &filter, false)
+            .unwrap()
+            .unwrap();
+        assert_eq!(diff.added[0].name(), "C");
+        assert_eq!(diff.removed[0].name(), "B");
+        assert_eq!(diff.modified[0].1.name(), "A renamed");
+        assert_eq!(servers.count_by_group("g"), 2);
+
+        assert!(servers
+            .update_by_group_name("g", &vec![], &filter, false)
+            .unwrap()
+            .is_none());
+        assert_eq!(servers.count_by_group("g"), 2);
+    }
+
+    #[test]
+    fn skip_and_remove_duplicates() {
+        let mut servers = Servers {
+            filepath: String::new(),
+            servers: vec![],
+        };
+        let filter = ServerFilter::default();
+        let a = vec![
+            trojan("trojan://p1@a.example.com:443#A"),
+            trojan("trojan://p2@b.example.com:443#B"),
+        ];
+        let b = vec![
+            trojan("trojan://p2@b.example.com:443#B%20resold"),
+            trojan("trojan://p3@c.example.com:443#C"),
+        ];
+
+        servers
+            .update_by_group_name("a", &a, &filter, false)
+            .unwrap();
+        servers
+            .update_by_group_name("b", &b, &filter, false)
+            .unwrap();
+        assert_eq!(servers.duplicates(), vec![2]);
+
+        let removed = servers.dedupe();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].group, "b");
+        assert!(servers.duplicates().is_empty());
+
+        servers.remove_group("b");
+        let diff = servers
+            .update_by_group_name("b", &b, &filter, true)
+            .unwrap()
+            .unwrap();
+        assert_eq!(diff.skipped.len(), 1);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(servers.count_by_group("b"), 1);
+    }
+
+    #[test]
+    fn remove_group() {
+        let mut servers = Servers {
+            filepath: String::new(),
+            servers: vec![],
+        };
+        let filter = ServerFilter::default();
+        let a = vec![
+            trojan("trojan://p1@a.example.com:443#A"),
+            trojan("trojan://p2@b.example.com:443#B"),
+        ];
+        let b = vec![trojan("trojan://p3@c.example.com:443#C")];
+        servers
+            .update_by_group_name("a", &a, &filter, false)
+            .unwrap();
+        servers
+            .update_by_group_name("b", &b, &filter, false)
+            .unwrap();
+
+        assert_eq!(servers.remove_group("a"), 2);
+        assert_eq!(servers.remove_group("a"), 0);
+        assert_eq!(servers.count_by_group("a"), 0);
+        assert_eq!(servers.count_by_group("b"), 1);
+    }
+
+    #[test]
+    fn rename_group() {
+        let mut servers = Servers {
+            filepath: String::new(),
+            servers: vec![],
+        };
+        let filter = ServerFilter::default();
+        let a = vec![
+            trojan("trojan://p1@a.example.com:443#A"),
+            trojan("trojan://p2@b.example.com:443#B"),
+        ];
+        let b = vec![trojan("trojan://p3@c.example.com:443#C")];
+        servers
+            .update_by_group_name("a", &a, &filter, false)
+            .unwrap();
+        servers
+            .update_by_group_name("b", &b, &filter, false)
+            .unwrap();
+
+        servers.rename_group("a", "c");
+        assert_eq!(servers.count_by_group("a"), 0);
+        assert_eq!(servers.count_by_group("c"), 2);
+        assert_eq!(servers.count_by_group("b"), 1);
+        assert_eq!(servers.get(0).unwrap().server.name(), "A");
+    }
+}
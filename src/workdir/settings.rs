@@ -1,8 +1,11 @@
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
 
 use crate::errors::kind::ErrorKind;
 use crate::errors::Error;
+use crate::v2ray::config::Config;
 use crate::v2ray::server::*;
 use std::fs::File;
 use std::time::SystemTime;
@@ -25,7 +28,396 @@ pub struct Subscription {
     pub url: String,
     pub added_at: chrono::DateTime<chrono::Local>,
     pub last_updated_at: chrono::DateTime<chrono::Local>,
+    /// last time the subscription was fetched, whether or not anything changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checked_at: Option<chrono::DateTime<chrono::Local>>,
     pub skip_update: bool,
+    /// how often the worker refreshes the subscription, e.g. 6h, defaults
+    /// to `subscription_defaults.update_interval`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_interval: Option<Interval>,
+    /// traffic quota reported by the provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// how to reach the subscription url, defaults to `subscription_defaults.via`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub via: Option<Via>,
+    /// User-Agent sent to the provider, some providers pick the format
+    /// by it, e.g. clash, v2rayN or shadowrocket
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// extra request headers, e.g. an Authorization header
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// request timeout in seconds, defaults to `subscription_defaults.timeout`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// which servers to keep and how to name them
+    #[serde(flatten)]
+    pub filter: ServerFilter,
+}
+
+/// Filters and renames the servers of a subscription before they are stored
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct ServerFilter {
+    /// keep only servers whose name matches this regex
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<String>,
+    /// drop servers whose name matches this regex, e.g. "剩余流量|官网"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<String>,
+    /// find/replace rules applied in order to the names of kept servers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rename: Vec<RenameRule>,
+    /// remove emoji flags like 🇭🇰 from server names
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strip_flags: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
+pub struct RenameRule {
+    pub pattern: String,
+    /// replacement, may refer to capture groups as $1 or ${name}
+    #[serde(default)]
+    pub replace: String,
+}
+
+impl ServerFilter {
+    /// Returns the kept servers with their new names. include and exclude
+    /// match the names given by the provider, before any renaming.
+    pub fn apply(&self, servers: &[ServerType]) -> Result<Vec<ServerType>, Error> {
+        let include = compile(self.include.as_deref())?;
+        let exclude = compile(self.exclude.as_deref())?;
+        let mut rename = vec![];
+        for rule in self.rename.iter() {
+            rename.push((compile(Some(rule.pattern.as_str()))?.unwrap(), rule));
+        }
+
+        let mut kept = vec![];
+        for server in servers.iter() {
+            let name = server.name();
+            if include.as_ref().is_some_and(|re| !re.is_match(name)) {
+                continue;
+            }
+            if exclude.as_ref().is_some_and(|re| re.is_match(name)) {
+                continue;
+            }
+
+            let mut new_name = String::from(name);
+            for (re, rule) in rename.iter() {
+                new_name = re
+                    .replace_all(new_name.as_str(), rule.replace.as_str())
+                    .into_owned();
+            }
+            if self.strip_flags {
+                new_name = strip_flags(new_name.as_str());
+            }
+
+            let mut server = server.clone();
+            if !new_name.is_empty() {
+                server.set_name(new_name);
+            }
+            kept.push(server);
+        }
+
+        Ok(kept)
+    }
+}
+
+fn compile(pattern: Option<&str>) -> Result<Option<Regex>, Error> {
+    match pattern {
+        Some(pattern) => match Regex::new(pattern) {
+            Ok(re) => Ok(Some(re)),
+            Err(err) => Err(Error {
+                kind: ErrorKind::InvalidRegex,
+                message: format!("invalid regex {}: {}", pattern, err),
+            }),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Emoji flags are pairs of regional indicator symbols
+fn strip_flags(name: &str) -> String {
+    name.chars()
+        .filter(|c| !('\u{1F1E6}'..='\u{1F1FF}').contains(c))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Settings shared by all subscriptions unless overridden per subscription
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SubscriptionDefaults {
+    pub via: Via,
+    /// request timeout in seconds
+    pub timeout: u64,
+    /// retries with exponential backoff on timeouts, connection errors
+    /// and 5xx responses, at most 10
+    pub retries: u32,
+    /// how many subscriptions are fetched at the same time
+    pub concurrency: usize,
+    /// leave out servers another subscription already provides
+    pub skip_duplicates: bool,
+    /// how often the worker refreshes subscriptions, never when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_interval: Option<Interval>,
+}
+
+impl Default for SubscriptionDefaults {
+    fn default() -> Self {
+        SubscriptionDefaults {
+            via: Via::Direct,
+            timeout: 30,
+            retries: 3,
+            concurrency: 4,
+            skip_duplicates: false,
+            update_interval: None,
+        }
+    }
+}
+
+impl Subscription {
+    /// Whether the worker should refresh the subscription at `now`
+    pub fn is_due(
+        &self,
+        defaults: &SubscriptionDefaults,
+        now: chrono::DateTime<chrono::Local>,
+    ) -> bool {
+        if self.skip_update {
+            return false;
+        }
+
+        let interval = match self
+            .update_interval
+            .as_ref()
+            .or(defaults.update_interval.as_ref())
+        {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let last = match self.last_checked_at {
+            Some(checked) if checked > self.last_updated_at => checked,
+            _ => self.last_updated_at,
+        };
+        now.signed_duration_since(last).num_seconds()
+            >= i64::try_from(interval.seconds).unwrap_or(i64::MAX)
+    }
+}
+
+/// A duration written as a number with a unit, e.g. 90s, 30m, 6h or 1d
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Interval {
+    pub seconds: u64,
+}
+
+impl TryFrom<String> for Interval {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let err = || Error {
+            kind: ErrorKind::InvalidInterval,
+            message: format!("invalid interval {}, expect e.g. 90s, 30m, 6h or 1d", value),
+        };
+
+        let value = value.trim();
+        let split = value.len() - value.chars().last().map_or(0, |c| c.len_utf8());
+        let unit = match &value[split..] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(err()),
+        };
+        // seconds are compared with signed timestamps, so keep them in i64
+        let seconds = value[..split]
+            .parse::<u64>()
+            .ok()
+            .filter(|number| *number > 0)
+            .and_then(|number| number.checked_mul(unit))
+            .filter(|seconds| i64::try_from(*seconds).is_ok());
+        match seconds {
+            Some(seconds) => Ok(Interval { seconds }),
+            None => Err(err()),
+        }
+    }
+}
+
+impl From<Interval> for String {
+    fn from(interval: Interval) -> Self {
+        let units = [(24 * 60 * 60, "d"), (60 * 60, "h"), (60, "m")];
+        for (size, unit) in units.iter() {
+            if interval.seconds.is_multiple_of(*size) {
+                return format!("{}{}", interval.seconds / size, unit);
+            }
+        }
+        format!("{}s", interval.seconds)
+    }
+}
+
+/// Route of subscription requests
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Via {
+    /// connect to the subscription url directly
+    #[default]
+    Direct,
+    /// go through the local http or socks inbound of v2ray.json
+    V2Ray,
+    /// go through a http, https, socks5 or socks5h proxy url
+    Proxy(String),
+}
+
+impl TryFrom<String> for Via {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "direct" => return Ok(Via::Direct),
+            "v2ray" => return Ok(Via::V2Ray),
+            _ => {}
+        }
+
+        match url::Url::parse(value.as_str()) {
+            Ok(url) if ["http", "https", "socks5", "socks5h"].contains(&url.scheme()) => {
+                Ok(Via::Proxy(value))
+            }
+            _ => Err(Error {
+                kind: ErrorKind::InvalidProxy,
+                message: format!(
+                    "invalid via {}, expect direct, v2ray or a http/socks5 proxy url",
+                    value
+                ),
+            }),
+        }
+    }
+}
+
+impl From<Via> for String {
+    fn from(via: Via) -> Self {
+        match via {
+            Via::Direct => String::from("direct"),
+            Via::V2Ray => String::from("v2ray"),
+            Via::Proxy(url) => url,
+        }
+    }
+}
+
+impl Via {
+    /// Resolves the proxy url to use, None for direct connections
+    pub fn proxy_url(&self, config: &Config) -> Result<Option<String>, Error> {
+        match self {
+            Via::Direct => Ok(None),
+            Via::Proxy(url) => Ok(Some(url.clone())),
+            Via::V2Ray => match config.local_proxy_url() {
+                Some(url) => Ok(Some(url)),
+                None => Err(Error {
+                    kind: ErrorKind::InvalidProxy,
+                    message: String::from("no http or socks inbound found in v2ray.json"),
+                }),
+            },
+        }
+    }
+}
+
+/// warn when this share of the traffic quota has been used
+const USAGE_WARNING_RATIO: f64 = 0.9;
+/// warn when the subscription expires within this many days
+const EXPIRE_WARNING_DAYS: i64 = 7;
+
+/// Traffic quota and expiry from the `subscription-userinfo` response header
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, Clone)]
+pub struct Usage {
+    pub upload: u64,
+    pub download: u64,
+    pub total: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire_at: Option<chrono::DateTime<chrono::Local>>,
+}
+
+impl Usage {
+    /// Parses a header like `upload=455727941; download=6174315083; total=1073741824000; expire=1671815872`
+    pub fn from_header(value: &str) -> Option<Usage> {
+        let mut usage = Usage::default();
+        let mut found = false;
+
+        for pair in value.split(';') {
+            let (key, value) = match pair.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => continue,
+            };
+            // some providers send floats like 1.073741824e+12
+            let number = match value.parse::<f64>() {
+                Ok(number) if number >= 0.0 => number as u64,
+                _ => continue,
+            };
+
+            match key {
+                "upload" => usage.upload = number,
+                "download" => usage.download = number,
+                "total" => usage.total = number,
+                "expire" => {
+                    usage.expire_at =
+                        chrono::TimeZone::timestamp_opt(&chrono::Local, number as i64, 0)
+                            .single()
+                            .filter(|_| number > 0)
+                }
+                _ => continue,
+            }
+            found = true;
+        }
+
+        match found {
+            true => Some(usage),
+            false => None,
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        // absurd floats from a provider parse to u64::MAX
+        self.upload.saturating_add(self.download)
+    }
+
+    /// Returns a warning when the quota is nearly used up or the
+    /// subscription is about to expire
+    pub fn warning(&self) -> Option<String> {
+        if self.total > 0 && self.used() as f64 >= self.total as f64 * USAGE_WARNING_RATIO {
+            return Some(format!(
+                "traffic used {} of {}",
+                format_bytes(self.used()),
+                format_bytes(self.total)
+            ));
+        }
+
+        if let Some(expire_at) = self.expire_at {
+            let left = expire_at - chrono::Local::now();
+            if left < chrono::Duration::zero() {
+                return Some(format!("expired at {}", expire_at.format("%Y-%m-%d")));
+            }
+            if left < chrono::Duration::days(EXPIRE_WARNING_DAYS) {
+                return Some(format!("expires at {}", expire_at.format("%Y-%m-%d")));
+            }
+        }
+
+        None
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} {}", bytes, units[0]),
+        _ => format!("{:.2} {}", value, units[unit]),
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -37,14 +429,120 @@ pub struct Settings {
     pub v2ray: V2Ray,
     pub log: Log,
     pub subscriptions: Vec<Subscription>,
+    #[serde(default)]
+    pub subscription_defaults: SubscriptionDefaults,
+    /// what to use when the selected server disappears from its subscription
+    #[serde(default)]
+    pub selection_fallback: SelectionFallback,
+    #[serde(default)]
+    pub probe: ProbeSettings,
+    /// lets the worker switch servers when the selected one fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_select: Option<AutoSelect>,
+}
+
+/// How the worker health-checks the selected server and picks another one
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct AutoSelect {
+    /// how often the selected server is checked
+    pub interval: Interval,
+    /// only pick from servers of the group
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// only pick from servers whose name matches the regex
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// check and rank through a temporary v2ray instead of tcp connects
+    #[serde(default)]
+    pub real: bool,
+}
+
+/// How `servers ping --real` tests servers end to end
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProbeSettings {
+    /// url requested through each server
+    pub url: String,
+    /// timeout in seconds of starting v2ray and of the request
+    pub timeout: u64,
+}
+
+impl Default for ProbeSettings {
+    fn default() -> Self {
+        ProbeSettings {
+            url: String::from("https://www.gstatic.com/generate_204"),
+            timeout: 10,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectionFallback {
+    /// keep the outbound in v2ray.json and only warn
+    #[default]
+    None,
+    /// switch to the first server of the same group
+    Group,
+    /// switch to the first server of all
+    Any,
 }
 
 impl Settings {
     pub fn add_subscription(&mut self, subscription: Subscription) -> Result<(), Error> {
+        if self
+            .find_subscription_mut(subscription.name.as_str())
+            .is_ok()
+        {
+            return Err(Error {
+                kind: ErrorKind::SubscriptionAlreadyExists,
+                message: format!("subscription {} already exists", subscription.name),
+            });
+        }
+
         self.subscriptions.push(subscription);
         self.save()
     }
 
+    pub fn find_subscription_mut(&mut self, name: &str) -> Result<&mut Subscription, Error> {
+        match self.subscriptions.iter_mut().find(|s| s.name.eq(name)) {
+            Some(subscription) => Ok(subscription),
+            None => Err(Error {
+                kind: ErrorKind::SubscriptionNotFound,
+                message: format!("subscription {} not found", name),
+            }),
+        }
+    }
+
+    pub fn rename_subscription(&mut self, name: &str, new_name: &str) -> Result<(), Error> {
+        if self.find_subscription_mut(new_name).is_ok() {
+            return Err(Error {
+                kind: ErrorKind::SubscriptionAlreadyExists,
+                message: format!("subscription {} already exists", new_name),
+            });
+        }
+
+        let subscription = self.find_subscription_mut(name)?;
+        subscription.name = String::from(new_name);
+        self.save()
+    }
+
+    pub fn remove_subscription(&mut self, name: &str) -> Result<Subscription, Error> {
+        let idx = match self.subscriptions.iter().position(|s| s.name.eq(name)) {
+            Some(idx) => idx,
+            None => {
+                return Err(Error {
+                    kind: ErrorKind::SubscriptionNotFound,
+                    message: format!("subscription {} not found", name),
+                })
+            }
+        };
+
+        let subscription = self.subscriptions.remove(idx);
+        self.save()?;
+        Ok(subscription)
+    }
+
     pub fn save(&self) -> Result<(), Error> {
         let result = serde_yaml::to_string(self);
         if result.is_err() {
@@ -85,3 +583,215 @@ impl Settings {
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(name: &str) -> Settings {
+        let mut settings: Settings = serde_yaml::from_str(
+            "v2ray:\n  bin: ''\nlog:\n  location: ''\nsubscriptions:\n  - name: a\n    url: http://a.example.com\n    added_at: 2023-01-01T00:00:00Z\n    last_updated_at: 2023-01-01T00:00:00Z\n    skip_update: false\n",
+        )
+        .unwrap();
+        settings.filepath = String::from(
+            std::env::temp_dir()
+                .join(format!("v2up-settings-{}-{}", name, std::process::id()))
+                .to_str()
+                .unwrap(),
+        );
+        settings
+    }
+
+    #[test]
+    fn remove_subscription() {
+        let mut settings = settings("remove");
+
+        assert!(matches!(
+            settings.remove_subscription("b").unwrap_err().kind,
+            ErrorKind::SubscriptionNotFound
+        ));
+        assert_eq!(settings.remove_subscription("a").unwrap().name, "a");
+        assert!(settings.subscriptions.is_empty());
+
+        let saved = fs::read_to_string(settings.filepath.as_str()).unwrap();
+        assert!(!saved.contains("http://a.example.com"));
+        let _ = fs::remove_file(settings.filepath.as_str());
+    }
+
+    #[test]
+    fn add_and_rename_subscription() {
+        let mut settings = settings("rename");
+        let mut subscription = settings.subscriptions[0].clone();
+        subscription.url = String::from("http://b.example.com");
+
+        assert!(matches!(
+            settings
+                .add_subscription(subscription.clone())
+                .unwrap_err()
+                .kind,
+            ErrorKind::SubscriptionAlreadyExists
+        ));
+        subscription.name = String::from("b");
+        settings.add_subscription(subscription).unwrap();
+        assert_eq!(settings.subscriptions.len(), 2);
+
+        assert!(matches!(
+            settings.rename_subscription("a", "b").unwrap_err().kind,
+            ErrorKind::SubscriptionAlreadyExists
+        ));
+        assert!(matches!(
+            settings.rename_subscription("c", "d").unwrap_err().kind,
+            ErrorKind::SubscriptionNotFound
+        ));
+        settings.rename_subscription("a", "c").unwrap();
+        assert_eq!(settings.subscriptions[0].name, "c");
+        assert_eq!(settings.subscriptions[0].url, "http://a.example.com");
+
+        let saved = fs::read_to_string(settings.filepath.as_str()).unwrap();
+        assert!(saved.contains("name: c"));
+        let _ = fs::remove_file(settings.filepath.as_str());
+    }
+
+    #[test]
+    fn parse_subscription_userinfo() {
+        let usage = Usage::from_header(
+            "upload=455727941; download=6174315083; total=1073741824000; expire=1671815872",
+        )
+        .unwrap();
+
+        assert_eq!(usage.upload, 455727941);
+        assert_eq!(usage.download, 6174315083);
+        assert_eq!(usage.used(), 6630043024);
+        assert_eq!(usage.total, 1073741824000);
+        assert_eq!(usage.expire_at.unwrap().timestamp(), 1671815872);
+        assert_eq!(usage.warning(), Some(String::from("expired at 2022-12-23")));
+    }
+
+    #[test]
+    fn parse_via() {
+        assert_eq!(Via::try_from(String::from("direct")).unwrap(), Via::Direct);
+        assert_eq!(Via::try_from(String::from("v2ray")).unwrap(), Via::V2Ray);
+        assert_eq!(
+            Via::try_from(String::from("socks5h://127.0.0.1:1080")).unwrap(),
+            Via::Proxy(String::from("socks5h://127.0.0.1:1080"))
+        );
+        assert!(Via::try_from(String::from("ftp://127.0.0.1")).is_err());
+        assert!(Via::try_from(String::from("proxy")).is_err());
+    }
+
+    #[test]
+    fn parse_subscription_userinfo_without_expire() {
+        let usage =
+            Usage::from_header("upload=0;download=1000000000;total=1.073741824e+9;").unwrap();
+
+        assert_eq!(usage.total, 1073741824);
+        assert_eq!(usage.expire_at, None);
+        assert_eq!(
+            usage.warning(),
+            Some(String::from("traffic used 953.67 MB of 1.00 GB"))
+        );
+        assert_eq!(Usage::from_header("garbage"), None);
+
+        let usage = Usage::from_header("upload=1e20; download=5; total=100").unwrap();
+        assert_eq!(usage.used(), u64::MAX);
+        assert!(usage.warning().unwrap().starts_with("traffic used"));
+    }
+
+    #[test]
+    fn filter_and_rename_servers() {
+        let servers: Vec<ServerType> = [
+            "trojan://p@hk.example.com:443#%F0%9F%87%AD%F0%9F%87%B0%20HK%2001%20%5B1.5x%5D",
+            "trojan://p@jp.example.com:443#%F0%9F%87%AF%F0%9F%87%B5%20JP%2001",
+            "trojan://p@ru.example.com:443#RU%2001",
+            "trojan://p@example.com:443#%E5%89%A9%E4%BD%99%E6%B5%81%E9%87%8F%3A%2099%20GB",
+        ]
+        .iter()
+        .map(|link| from_str(link).unwrap())
+        .collect();
+
+        let filter = ServerFilter {
+            include: None,
+            exclude: Some(String::from("剩余流量|官网|^RU")),
+            rename: vec![RenameRule {
+                pattern: String::from(r"\s*\[[\d.]+x\]"),
+                replace: String::new(),
+            }],
+            strip_flags: true,
+        };
+        let names: Vec<String> = filter
+            .apply(&servers)
+            .unwrap()
+            .iter()
+            .map(|server| String::from(server.name()))
+            .collect();
+        assert_eq!(names, vec!["HK 01", "JP 01"]);
+
+        let filter = ServerFilter {
+            include: Some(String::from("JP")),
+            ..Default::default()
+        };
+        let kept = filter.apply(&servers).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].name(), "🇯🇵 JP 01");
+
+        let filter = ServerFilter {
+            include: Some(String::from("(")),
+            ..Default::default()
+        };
+        assert!(filter.apply(&servers).is_err());
+    }
+
+    #[test]
+    fn parse_interval() {
+        let interval = Interval::try_from(String::from("6h")).unwrap();
+        assert_eq!(interval.seconds, 6 * 60 * 60);
+        assert_eq!(String::from(interval), "6h");
+        assert_eq!(
+            String::from(Interval::try_from(String::from("90m")).unwrap()),
+            "90m"
+        );
+        assert_eq!(
+            String::from(Interval::try_from(String::from("120s")).unwrap()),
+            "2m"
+        );
+        assert!(Interval::try_from(String::from("6")).is_err());
+        assert!(Interval::try_from(String::from("0h")).is_err());
+        assert!(Interval::try_from(String::from("h")).is_err());
+    }
+
+    #[test]
+    fn reject_overflowing_interval() {
+        assert!(Interval::try_from(String::from("999999999999999999d")).is_err());
+        assert!(Interval::try_from(format!("{}s", u64::MAX)).is_err());
+        assert!(Interval::try_from(format!("{}s", i64::MAX as u64 + 1)).is_err());
+
+        let interval = Interval::try_from(format!("{}s", i64::MAX)).unwrap();
+        assert_eq!(interval.seconds, i64::MAX as u64);
+
+        let yaml = "update_interval: 999999999999999999d";
+        assert!(serde_yaml::from_str::<SubscriptionDefaults>(yaml).is_err());
+    }
+
+    #[test]
+    fn subscription_due() {
+        let now = chrono::Local::now();
+        let mut sub: Subscription = serde_yaml::from_str(
+            "name: a\nurl: http://example.com\nadded_at: 2023-01-01T00:00:00Z\nlast_updated_at: 2023-01-01T00:00:00Z\nskip_update: false\n",
+        )
+        .unwrap();
+        let mut defaults = SubscriptionDefaults::default();
+        assert!(!sub.is_due(&defaults, now));
+
+        defaults.update_interval = Some(Interval { seconds: 3600 });
+        assert!(sub.is_due(&defaults, now));
+
+        sub.last_checked_at = Some(now - chrono::Duration::minutes(30));
+        assert!(!sub.is_due(&defaults, now));
+
+        sub.update_interval = Some(Interval { seconds: 600 });
+        assert!(sub.is_due(&defaults, now));
+
+        sub.skip_update = true;
+        assert!(!sub.is_due(&defaults, now));
+    }
+}